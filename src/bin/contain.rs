@@ -1,12 +1,17 @@
-use std::process::Command;
+use std::process::{exit, Command};
 use std::str::FromStr;
 
 use log::{debug, error, LevelFilter};
 use redox_log::{OutputBuilder, RedoxLogger};
 
-use contain::{run_contained, ContainConfig};
+use contain::{
+    diagnostics_to_json, exec_registered, explain, find_container, lint_config, lint_file,
+    list_containers, list_profiles, load_profile, run_registered, select_user_config,
+    validate_config, ConfigVars, ContainConfig, ContainError, EnvConfig, Operation, RunAs,
+    PROFILE_DIR, RUN_DIR,
+};
 
-use clap::{Args, Parser};
+use clap::{Args, Parser, Subcommand};
 use redox_users::All;
 
 /// Contain: Limit the access to the file system.
@@ -22,61 +27,315 @@ use redox_users::All;
 ///
 /// If there is no root, cwd is mandatory
 /// and is added to the allowed directories.
+///
+/// `contain run` is the default, so `contain --cwd DIR COMMAND`
+/// is the same as `contain run --cwd DIR COMMAND`.
+/// Named profiles, e.g. `--profile shell --profile network-client`,
+/// are merged into the config. `contain profiles list` shows them.
+/// `--dry-run[=toml|ron]` prints the final config instead of running the command.
+/// `contain check` validates the final config without running anything.
+/// `contain lint [--json] [FILE]` checks a policy file for problems.
+/// `contain explain [--write|--create|--unlink] PATH` shows which rule
+/// allows or denies the path.
+/// `contain` exits with the command's exit code, or 128 + the signal that killed it.
+/// SIGINT, SIGTERM, SIGHUP, SIGQUIT, SIGUSR1 and SIGUSR2 are passed on to the
+/// command, and a second SIGINT kills the container.
+/// With `--timeout`, the container is killed when the time is up,
+/// and contain exits with 124.
+/// `contain ps` lists the running containers, `contain exec NAME COMMAND`
+/// runs another command in one and `contain stop NAME` stops it.
+/// The command gets only the environment variables that the config's `[env]`
+/// allows, see `--env`, `--unset-env` and `--clear-env`.
+/// With `--user`, the user's and their groups' configs from
+/// /etc/contain.d/users and /etc/contain.d/groups are merged in too.
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct ContainArgs {
+    #[command(subcommand)]
+    action: Option<Action>,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Debug level ("error", "warn", "info", "debug", or "trace")
+    #[arg(long, global = true)]
+    debug: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Run a command in a new container (the default)
+    Run(RunArgs),
+    /// Run a command in a running container, as the container's user,
+    /// with its `[env]` policy, `on_exit` and timeout
+    Exec {
+        /// Working directory in the container
+        #[arg(short, long)]
+        cwd: Option<String>,
+        /// The name of the container, see `contain ps`
+        name: String,
+        /// Command to be executed, and its args
+        #[arg(required = true)]
+        command: Vec<String>,
+    },
+    /// Validate the final config without running anything
+    Check {
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// "chroot", using the specified directory as root
+        #[arg(short, long)]
+        root: Option<String>,
+    },
+    /// Show which rule allows or denies a path, without running anything
+    Explain {
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// "chroot", using the specified directory as root
+        #[arg(short, long)]
+        root: Option<String>,
+        /// Check opening the path for writing
+        #[arg(long, group = "operation")]
+        write: bool,
+        /// Check creating the path
+        #[arg(long, group = "operation")]
+        create: bool,
+        /// Check removing the path
+        #[arg(long, group = "operation")]
+        unlink: bool,
+        /// The path to check, e.g. "file:/home/user/file" or "/home/user/file"
+        path: String,
+    },
+    /// List the running containers
+    Ps,
+    /// Stop a running container
+    Stop {
+        /// The name of the container, see `contain ps`
+        name: String,
+    },
+    /// Named profiles
+    Profiles {
+        #[command(subcommand)]
+        action: ProfilesAction,
+    },
+    /// Check a policy file for problems, without running anything
+    Lint {
+        /// Print the problems as JSON
+        #[arg(long)]
+        json: bool,
+        /// The toml/ron file to check, default /etc/contain.toml
+        file: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfilesAction {
+    /// List the available profiles
+    List,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
     #[command(flatten)]
     working_dir: WorkingDir,
 
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Name for the container, for `contain exec`, `ps` and `stop`.
+    /// The default is the pid of contain
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Print the final config as "toml" or "ron" and exit, without running anything
+    #[arg(
+        long,
+        alias = "print-config",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "toml",
+        value_parser = ["toml", "ron"]
+    )]
+    dry_run: Option<String>,
+
+    /// Command to be executed, and its args - evaluated after chroot (if any)
+    /// If a user is specified, the command is optional
+    command: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+#[group(required = true)]
+struct WorkingDir {
+    /// "chroot", using the specified directory as root (optional)
+    #[arg(short, long)]
+    root: Option<String>,
+
+    /// Working directory - required if there is no chroot
+    #[arg(short, long)]
+    cwd: Option<String>,
+}
+
+/// The options that make up the config
+#[derive(Args, Debug)]
+struct ConfigArgs {
     /// Don't include default files or schemes
     #[arg(long)]
     no_default: bool,
 
+    /// Fail if any config entry is invalid, instead of dropping it with a warning
+    #[arg(long)]
+    strict: bool,
+
     /// The toml/ron file containing the default config
-    #[arg(short, long)]
+    #[arg(long)]
     config: Option<String>,
 
+    /// Named profile(s) to add to the config, from /etc/contain.d/profiles.
+    /// Use with --no-default to replace the default config
+    #[arg(long)]
+    profile: Vec<String>,
+
     /// Set User ID for the command to execute
     #[arg(short, long)]
     user: Option<String>,
 
     /// Schemes to allow unchanged (don't include trailing ":")
-    #[arg(short, long)]
+    #[arg(short, long, value_parser = parse_scheme)]
     pass_schemes: Vec<String>,
 
     /// Schemes to be filtered (only specified paths)
-    #[arg(short, long)]
+    #[arg(short, long, value_parser = parse_scheme)]
     sandbox_schemes: Vec<String>,
 
     /// Add a file or match to allow, format "scheme:/dir/dir/file"
-    #[arg(short, long)]
+    #[arg(short, long, value_parser = parse_path)]
     file: Vec<String>,
 
     /// Include directory(s) or prefix to allow unchanged, format "scheme:/dir/dir"
-    #[arg(short, long)]
+    #[arg(short, long, value_parser = parse_path)]
     dir: Vec<String>,
 
-    /// Debug level ("error", "warn", "info", "debug", or "trace")
+    /// Add a file to allow read only, format "scheme:/dir/dir/file"
+    #[arg(long, value_parser = parse_path)]
+    rofile: Vec<String>,
+
+    /// Add a directory to allow read only, format "scheme:/dir/dir"
+    #[arg(long, value_parser = parse_path)]
+    rodir: Vec<String>,
+
+    /// Deny a directory and everything in it, overriding any allow
+    #[arg(long, value_parser = parse_path)]
+    deny: Vec<String>,
+
+    /// Deny an exact file, overriding any allow
+    #[arg(long, value_parser = parse_path)]
+    deny_file: Vec<String>,
+
+    /// Remove a pass scheme from the config
+    #[arg(long, value_parser = parse_scheme)]
+    no_pass_scheme: Vec<String>,
+
+    /// Remove a sandbox scheme from the config
+    #[arg(long, value_parser = parse_scheme)]
+    no_sandbox_scheme: Vec<String>,
+
+    /// Remove a file from the config
+    #[arg(long, value_parser = parse_path)]
+    no_file: Vec<String>,
+
+    /// Remove a directory from the config
+    #[arg(long, value_parser = parse_path)]
+    no_dir: Vec<String>,
+
+    /// Remove a read only file from the config
+    #[arg(long, value_parser = parse_path)]
+    no_rofile: Vec<String>,
+
+    /// Remove a read only directory from the config
+    #[arg(long, value_parser = parse_path)]
+    no_rodir: Vec<String>,
+
+    /// Set an environment variable with "NAME=VALUE",
+    /// or pass it on from this environment with "NAME"
+    #[arg(long, value_name = "NAME[=VALUE]", value_parser = parse_env)]
+    env: Vec<(String, Option<String>)>,
+
+    /// Remove an environment variable
+    #[arg(long, value_name = "NAME")]
+    unset_env: Vec<String>,
+
+    /// Pass no variables from this environment, not even those the config allows
     #[arg(long)]
-    debug: Option<String>,
+    clear_env: bool,
 
-    /// Command to be executed, and its args - evaluated after chroot (if any)
-    /// If a user is specified, the command is optional
-    command: Vec<String>,
+    /// Kill the container if the command runs longer than this,
+    /// e.g. "90", "90s", "15m" or "2h"
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<u64>,
 }
 
-#[derive(Args, Debug)]
-#[group(required = true)]
-struct WorkingDir {
-    /// "chroot", using the specified directory as root (optional)
-    #[arg(short, long)]
-    root: Option<String>,
+// Check a scheme name for the command line, e.g. "file", not "file:"
+fn parse_scheme(scheme: &str) -> Result<String, String> {
+    if scheme.is_empty() || scheme.contains([':', '/']) {
+        Err("scheme must be a name without ':' or '/', e.g. \"file\"".to_string())
+    } else {
+        Ok(scheme.to_string())
+    }
+}
 
-    /// Working directory - required if there is no chroot
-    #[arg(short, long)]
-    cwd: Option<String>,
+// Check a path for the command line, format "scheme:/dir/file"
+fn parse_path(path: &str) -> Result<String, String> {
+    match path.split_once(":/") {
+        Some((scheme, _)) if !scheme.is_empty() && !scheme.contains('/') => Ok(path.to_string()),
+        _ => Err("path must be in the form scheme:/path, e.g. \"file:/tmp\"".to_string()),
+    }
+}
+
+// Split an environment variable for the command line, "NAME=VALUE" or "NAME"
+fn parse_env(var: &str) -> Result<(String, Option<String>), String> {
+    let (name, value) = match var.split_once('=') {
+        Some((name, value)) => (name, Some(value.to_string())),
+        None => (var, None),
+    };
+    if name.is_empty() {
+        Err("environment variable must be NAME=VALUE or NAME".to_string())
+    } else {
+        Ok((name.to_string(), value))
+    }
+}
+
+// Parse a duration for the command line into seconds, e.g. "90", "90s", "15m" or "2h"
+fn parse_duration(duration: &str) -> Result<u64, String> {
+    let error = || "duration must be a number with s, m or h, e.g. \"15m\"".to_string();
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => duration.split_at(i),
+        None => (duration, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(error()),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .filter(|seconds| *seconds > 0)
+        .ok_or_else(error)
+}
+
+// Remove the entries from the list, ignoring trailing slashes
+fn remove_entries(list: &mut Vec<String>, entries: &[String]) {
+    list.retain(|item| {
+        !entries
+            .iter()
+            .any(|entry| entry.trim_end_matches('/') == item.trim_end_matches('/'))
+    });
 }
 
 const CONFIG_FILE: &str = "file:/etc/contain.toml";
+const CONFIG_DIR: &str = "file:/etc/contain.d";
 
 fn setup_logging(level: LevelFilter) -> Option<&'static RedoxLogger> {
     let mut logger = RedoxLogger::new().with_output(
@@ -125,104 +384,236 @@ fn setup_logging(level: LevelFilter) -> Option<&'static RedoxLogger> {
     }
 }
 
-pub fn main() {
-    let contain_args = ContainArgs::parse();
-
-    let log_level = if contain_args.debug.is_some() {
-        LevelFilter::from_str(contain_args.debug.as_ref().unwrap()).unwrap_or(LevelFilter::Error)
-    } else {
-        LevelFilter::Error
-    };
+// Print the name and description of each profile
+fn print_profiles() {
+    let profiles = list_profiles(PROFILE_DIR)
+        .map_err(|e| {
+            error!("could not list profiles in {}: {}", PROFILE_DIR, e);
+            eprintln!("could not list profiles in {}: {}", PROFILE_DIR, e);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    let width = profiles.iter().map(|p| p.name.len()).max().unwrap_or(0);
+    for profile in profiles {
+        println!(
+            "{:width$}  {}",
+            profile.name,
+            profile.description,
+            width = width
+        );
+    }
+}
 
-    setup_logging(log_level);
+// The config variables for the user running contain.
+// If there is no such user, any variable in the config is reported as unknown.
+fn current_user_vars() -> ConfigVars {
+    let user_vars = redox_users::get_uid().ok().and_then(|uid| {
+        redox_users::AllUsers::basic(redox_users::Config::default())
+            .ok()?
+            .get_by_id(uid)
+            .map(ConfigVars::from_user)
+    });
+    user_vars.unwrap_or_else(|| {
+        debug!("could not get the current user, config variables are unknown");
+        ConfigVars::default()
+    })
+}
 
-    debug!("contain_args: {:?}", contain_args);
+// Add the "file" scheme to a path that does not have one
+fn absolute_path(path: &str) -> String {
+    if path.contains(':') {
+        path.to_string()
+    } else {
+        format!("file:/{}", path.trim_start_matches('/'))
+    }
+}
 
-    let mut config = if contain_args.no_default && contain_args.config.is_none() {
+// Read the default config and drop-ins, or the config file, then add the profiles.
+// Exit if anything can't be read.
+fn load_config(
+    no_default: bool,
+    config_file: Option<&String>,
+    profiles: &[String],
+) -> ContainConfig {
+    let mut config = if no_default && config_file.is_none() {
         ContainConfig::default()
     } else {
-        let config_file = if let Some(config_file) = contain_args.config.as_ref() {
-            config_file.clone()
-        } else {
-            CONFIG_FILE.to_string()
-        };
-        ContainConfig::from_file(&config_file)
+        let filename = config_file.map(|f| f.as_str()).unwrap_or(CONFIG_FILE);
+        ContainConfig::from_file(filename)
+            .and_then(|config| {
+                // Drop-ins only extend the default config
+                if config_file.is_none() {
+                    config.with_drop_ins(CONFIG_DIR)
+                } else {
+                    Ok(config)
+                }
+            })
             .map_err(|e| {
-                error!("could not read config from file {}: {}", CONFIG_FILE, e);
-                eprintln!("could not read config from file {}: {}", CONFIG_FILE, e);
+                error!("could not read config from file {}: {}", filename, e);
+                eprintln!("could not read config from file {}: {}", filename, e);
                 let _ = syscall::exit(1);
             })
             .unwrap()
     };
 
-    debug!("config from file {}: {:?}", CONFIG_FILE, config);
+    for name in profiles.iter() {
+        let profile = load_profile(PROFILE_DIR, name)
+            .map_err(|e| {
+                error!("could not read profile {}: {}", name, e);
+                eprintln!("could not read profile {}: {}", name, e);
+                let _ = syscall::exit(1);
+            })
+            .unwrap();
+        config.merge(profile);
+    }
+    config
+}
 
-    let root = match contain_args.working_dir.root {
-        Some(root) if root.contains(':') => Some(root.clone()),
-        Some(root) => Some(format!("file:/{}", root.trim_start_matches('/'))),
-        None => None,
-    };
+// Validate the config and print any warnings, exit if it is not valid
+fn validate(config: ContainConfig) -> ContainConfig {
+    let (config, warnings) = validate_config(config)
+        .map_err(|e| {
+            match e {
+                ContainError::InvalidConfig(errors) => {
+                    for e in errors {
+                        eprintln!("contain: error: {}", e);
+                    }
+                }
+                e => eprintln!("contain: invalid config: {}", e),
+            }
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    for warning in warnings {
+        eprintln!("contain: warning: {}", warning);
+    }
+    config
+}
 
-    let cwd = match contain_args.working_dir.cwd {
-        Some(cwd) if cwd.contains(':') => Some(cwd.clone()),
-        Some(cwd) => Some(format!("file:/{}", cwd.trim_start_matches('/'))),
-        None => None,
+// Print the config as toml or ron
+fn print_config(config: &ContainConfig, format: &str) {
+    let config_str = if format == "ron" {
+        config.to_ron()
+    } else {
+        config.to_toml()
     };
-    assert!(root.is_some() || cwd.is_some()); // or both
+    let config_str = config_str
+        .map_err(|e| {
+            error!("could not print config: {}", e);
+            eprintln!("could not print config: {}", e);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    println!("{}", config_str);
+}
 
-    for s in contain_args.pass_schemes {
-        config.pass_schemes.push(s.to_string());
+// Print how a path would be handled in the container.
+// Exit with an error if access would be denied.
+fn print_explanation(config: &ContainConfig, path: &str, ops: &[Operation]) {
+    let rules = config
+        .rule_set()
+        .map_err(|e| {
+            error!("{}", e);
+            eprintln!("{}", e);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    let explanation = explain(config, &rules, path, ops);
+    let ops: Vec<String> = ops.iter().map(|op| op.to_string()).collect();
+    println!("path:      {} ({})", path, ops.join(", "));
+    if let Some(real_path) = explanation.real_path.as_ref() {
+        println!("real path: {}", real_path);
     }
-    for s in contain_args.sandbox_schemes {
-        config.sandbox_schemes.push(s.to_string());
+    if let Some(checked_path) = explanation.checked_path.as_ref() {
+        println!("checked:   {}", checked_path);
     }
-    for f in contain_args.file {
-        config.files.push(f.to_string());
-    }
-    for d in contain_args.dir {
-        config.dirs.push(d.to_string());
+    match (&explanation.decision, &explanation.result) {
+        (Some(decision), _) => {
+            let verdict = if decision.allowed {
+                "allowed"
+            } else {
+                "denied"
+            };
+            println!("decision:  {}", verdict);
+            println!("reason:    {}", decision.reason);
+        }
+        (None, Err(e)) => {
+            println!("decision:  denied");
+            println!("reason:    path can't be resolved, {}", e);
+        }
+        (None, Ok(_)) => {}
     }
-    // If there is a chroot, cwd is relative to root
-    // or otherwise allowed.
-    // If not, cwd is automatically allowed.
-    if cwd.is_some() && root.is_none() {
-        config.dirs.push(cwd.as_ref().unwrap().clone());
+    if explanation.result.is_err() {
+        let _ = syscall::exit(1);
     }
+}
 
-    assert!(config.files.iter().all(|f| f.contains(":/")));
-    assert!(config.dirs.iter().all(|d| d.contains(":/")));
-    assert!(config
-        .sandbox_schemes
-        .iter()
-        .all(|s| !s.contains([':', '/'])));
-    assert!(config.pass_schemes.iter().all(|s| !s.contains([':', '/'])));
+// Print the problems in a policy file, exit with an error if there are any
+fn lint(file: &str, json: bool) {
+    let diagnostics = lint_file(file, &current_user_vars())
+        .map_err(|e| {
+            error!("could not read config from file {}: {}", file, e);
+            eprintln!("could not read config from file {}: {}", file, e);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    if json {
+        println!("{}", diagnostics_to_json(&diagnostics));
+    } else {
+        for diagnostic in diagnostics.iter() {
+            println!("{}", diagnostic);
+        }
+        match diagnostics.len() {
+            0 => println!("{}: no problems found", file),
+            1 => println!("{}: 1 problem found", file),
+            n => println!("{}: {} problems found", file, n),
+        }
+    }
+    if !diagnostics.is_empty() {
+        let _ = syscall::exit(1);
+    }
+}
 
-    if contain_args.user.is_none() && contain_args.command.len() == 0 {
-        error!("User was not specified and no command was provided.");
-        eprintln!("User was not specified and no command was provided.");
+// Replace ${HOME} etc. in the config paths, exit if there is an unknown variable
+fn expand_vars(config: &mut ContainConfig, vars: &ConfigVars) {
+    if let Err(e) = config.expand_vars(vars) {
+        error!("could not expand config variables: {}", e);
+        eprintln!("could not expand config variables: {}", e);
         let _ = syscall::exit(1);
     }
+}
 
-    let mut command = if !contain_args.command.is_empty() {
-        let mut cmd_iter = contain_args.command.iter();
-        let mut command = Command::new(cmd_iter.next().unwrap());
-        for arg in cmd_iter {
-            command.arg(&arg);
-        }
-        command
-    } else {
-        // This is a placeholder command, to be filled in when we have the user info
-        Command::new("login")
-    };
+// Build the final config from the config files, profiles and options,
+// for the user if there is one, and validate it.
+// `root` and `cwd` are absolute paths. Exit if anything fails.
+fn build_config(
+    args: ConfigArgs,
+    root: Option<&String>,
+    cwd: Option<&String>,
+) -> (ContainConfig, Option<RunAs>) {
+    let mut config = load_config(args.no_default, args.config.as_ref(), &args.profile);
 
-    if cwd.is_some() {
-        command.current_dir(format!("{}", cwd.as_ref().unwrap()));
-    } else {
-        // This is a placeholder command, to be filled in when we have the user info
-        command.current_dir("/");
+    debug!("config from file {}: {:?}", CONFIG_FILE, config);
+
+    config.pass_schemes.extend(args.pass_schemes);
+    config.sandbox_schemes.extend(args.sandbox_schemes);
+    config.files.extend(args.file);
+    config.dirs.extend(args.dir);
+    config.rofiles.extend(args.rofile);
+    config.rodirs.extend(args.rodir);
+    config.deny_dirs.extend(args.deny);
+    config.deny_files.extend(args.deny_file);
+    if let Some(root) = root {
+        config.add_chroot(root);
+    }
+    // If there is a chroot, cwd is relative to root
+    // or otherwise allowed.
+    // If not, cwd is automatically allowed.
+    if let (Some(cwd), None) = (cwd, root) {
+        config.dirs.push(cwd.clone());
     }
 
-    if contain_args.user.is_some() {
+    let run_as = if let Some(user_name) = args.user.as_ref() {
         let all_users = redox_users::AllUsers::authenticator(redox_users::Config::default())
             .map_err(|e| {
                 error!("failed to get authenticator, {}", e);
@@ -231,23 +622,265 @@ pub fn main() {
             })
             .unwrap();
         let user = all_users
-            .get_by_name(contain_args.user.as_ref().unwrap())
+            .get_by_name(user_name)
             .ok_or_else(|| {
-                error!("could not get user {}", contain_args.user.as_ref().unwrap());
-                eprintln!("could not get user {}", contain_args.user.as_ref().unwrap());
+                error!("could not get user {}", user_name);
+                eprintln!("could not get user {}", user_name);
                 let _ = syscall::exit(1);
             })
             .unwrap();
 
-        if contain_args.command.is_empty() {
-            command = user.shell_cmd();
+        // The user and group configs only extend the default config.
+        // Unlike the login programs, root is contained when asked for.
+        if args.config.is_none() && !args.no_default {
+            config = select_user_config(config, user)
+                .map_err(|e| {
+                    error!("could not select config for user {}: {}", user.user, e);
+                    eprintln!("could not select config for user {}: {}", user.user, e);
+                    let _ = syscall::exit(1);
+                })
+                .unwrap();
         }
 
+        expand_vars(&mut config, &ConfigVars::from_user(user));
+
         if cwd.is_none() {
-            command.current_dir(&user.home);
             config.add_dir(&user.home)
         }
+        Some(
+            RunAs::from_user(user)
+                .map_err(|e| {
+                    error!("could not get the groups of user {}: {}", user.user, e);
+                    eprintln!("could not get the groups of user {}: {}", user.user, e);
+                    let _ = syscall::exit(1);
+                })
+                .unwrap(),
+        )
+    } else {
+        // Expand the config variables for the user running contain
+        expand_vars(&mut config, &current_user_vars());
+        None
+    };
+
+    // Remove entries once the variables are expanded
+    remove_entries(&mut config.pass_schemes, &args.no_pass_scheme);
+    remove_entries(&mut config.sandbox_schemes, &args.no_sandbox_scheme);
+    remove_entries(&mut config.files, &args.no_file);
+    remove_entries(&mut config.dirs, &args.no_dir);
+    remove_entries(&mut config.rofiles, &args.no_rofile);
+    remove_entries(&mut config.rodirs, &args.no_rodir);
+
+    if args.clear_env {
+        config.env.clear = Some(true);
+        config.env.allow.clear();
+    }
+    let mut env = EnvConfig {
+        unset: args.unset_env,
+        ..Default::default()
+    };
+    for (name, value) in args.env {
+        match value {
+            Some(value) => {
+                env.set.insert(name, value);
+            }
+            None => env.allow.push(name),
+        }
     }
+    config.env.merge(env);
 
-    let _ = run_contained(config, command);
+    if args.timeout.is_some() {
+        config.timeout = args.timeout;
+    }
+    if args.strict {
+        config.strict = Some(true);
+    }
+    (validate(config), run_as)
+}
+
+// Build a command from the command line args
+fn command_from_args(args: &[String]) -> Command {
+    let mut cmd_iter = args.iter();
+    let mut command = Command::new(cmd_iter.next().unwrap());
+    command.args(cmd_iter);
+    command
+}
+
+// Run a command in a new container
+fn run(args: RunArgs) {
+    let root = args.working_dir.root.as_deref().map(absolute_path);
+    let cwd = args.working_dir.cwd.as_deref().map(absolute_path);
+    assert!(root.is_some() || cwd.is_some()); // or both
+
+    if args.config.user.is_none() && args.command.is_empty() && args.dry_run.is_none() {
+        error!("User was not specified and no command was provided.");
+        eprintln!("User was not specified and no command was provided.");
+        let _ = syscall::exit(1);
+    }
+
+    let (config, run_as) = build_config(args.config, root.as_ref(), cwd.as_ref());
+
+    if let Some(format) = args.dry_run.as_deref() {
+        print_config(&config, format);
+        return;
+    }
+
+    let mut command = match run_as.as_ref() {
+        Some(run_as) if args.command.is_empty() => Command::new(&run_as.shell),
+        _ => command_from_args(&args.command),
+    };
+    match (cwd, run_as.as_ref()) {
+        (Some(cwd), _) => command.current_dir(cwd),
+        (None, Some(run_as)) => command.current_dir(&run_as.home),
+        (None, None) => command.current_dir("/"),
+    };
+
+    let name = args.name.as_deref();
+    let status = run_registered(config, command, RUN_DIR, name, run_as.as_ref())
+        .map_err(|e| {
+            error!("could not run the container: {}", e);
+            eprintln!("could not run the container: {}", e);
+            // like timeout(1)
+            let code = if matches!(e, ContainError::TimedOut) {
+                124
+            } else {
+                1
+            };
+            let _ = syscall::exit(code);
+        })
+        .unwrap();
+    debug!("command finished, {}", status);
+    exit(status.exit_code());
+}
+
+// Run a command in the namespace of a running container
+fn exec(name: &str, cwd: Option<&String>, command: &[String]) {
+    let info = find_container(RUN_DIR, name)
+        .ok()
+        .filter(|info| info.is_running())
+        .ok_or_else(|| {
+            error!("container {} is not running", name);
+            eprintln!("container {} is not running", name);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    let mut command = command_from_args(command);
+    if let Some(cwd) = cwd {
+        command.current_dir(absolute_path(cwd));
+    }
+    let status = exec_registered(&info, command)
+        .map_err(|e| {
+            error!("could not run in container {}: {}", name, e);
+            eprintln!("could not run in container {}: {}", name, e);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    debug!("command finished, {}", status);
+    exit(status.exit_code());
+}
+
+// Print the final config's problems as warnings.
+// The config was already validated, so it is usable.
+fn check(config: &ContainConfig) {
+    for diagnostic in lint_config(config) {
+        eprintln!("contain: warning: {}", diagnostic);
+    }
+    println!("config is valid");
+}
+
+// Print the running containers
+fn print_containers() {
+    let containers = list_containers(RUN_DIR)
+        .map_err(|e| {
+            error!("could not list containers in {}: {}", RUN_DIR, e);
+            eprintln!("could not list containers in {}: {}", RUN_DIR, e);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    let containers: Vec<_> = containers.into_iter().filter(|c| c.is_running()).collect();
+    let width = containers
+        .iter()
+        .map(|c| c.name.len())
+        .chain(["NAME".len()])
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:width$}  {:>6}  {:>9}  COMMAND",
+        "NAME", "PID", "NAMESPACE"
+    );
+    for container in containers {
+        println!(
+            "{:width$}  {:>6}  {:>9}  {}",
+            container.name, container.pid, container.namespace, container.command
+        );
+    }
+}
+
+// Stop a running container by sending SIGTERM to its contain process
+fn stop(name: &str) {
+    let info = find_container(RUN_DIR, name)
+        .ok()
+        .filter(|info| info.is_running())
+        .ok_or_else(|| {
+            error!("container {} is not running", name);
+            eprintln!("container {} is not running", name);
+            let _ = syscall::exit(1);
+        })
+        .unwrap();
+    let _ = syscall::kill(info.pid, syscall::SIGTERM).map_err(|e| {
+        error!("could not stop container {}: {}", name, e);
+        eprintln!("could not stop container {}: {}", name, e);
+        let _ = syscall::exit(1);
+    });
+}
+
+pub fn main() {
+    let contain_args = ContainArgs::parse();
+
+    let log_level = if contain_args.debug.is_some() {
+        LevelFilter::from_str(contain_args.debug.as_ref().unwrap()).unwrap_or(LevelFilter::Error)
+    } else {
+        LevelFilter::Error
+    };
+
+    setup_logging(log_level);
+
+    debug!("contain_args: {:?}", contain_args);
+
+    match contain_args.action {
+        Some(Action::Run(args)) => run(args),
+        Some(Action::Exec { name, cwd, command }) => exec(&name, cwd.as_ref(), &command),
+        Some(Action::Check { config, root }) => {
+            let root = root.as_deref().map(absolute_path);
+            let (config, _) = build_config(config, root.as_ref(), None);
+            check(&config);
+        }
+        Some(Action::Explain {
+            config,
+            root,
+            write,
+            create,
+            unlink,
+            path,
+        }) => {
+            let root = root.as_deref().map(absolute_path);
+            let (config, _) = build_config(config, root.as_ref(), None);
+            let ops = if write {
+                vec![Operation::Write]
+            } else if create {
+                vec![Operation::Write, Operation::Create]
+            } else if unlink {
+                vec![Operation::Unlink]
+            } else {
+                vec![Operation::Read]
+            };
+            print_explanation(&config, &path, &ops);
+        }
+        Some(Action::Ps) => print_containers(),
+        Some(Action::Stop { name }) => stop(&name),
+        Some(Action::Profiles {
+            action: ProfilesAction::List,
+        }) => print_profiles(),
+        Some(Action::Lint { json, file }) => lint(file.as_deref().unwrap_or(CONFIG_FILE), json),
+        None => run(contain_args.run),
+    }
 }
@@ -1,4 +1,4 @@
-use contain::{run_contained, run_not_contained, ContainConfig};
+use contain::{run_contained, run_not_contained, select_user_config, ConfigVars, ContainConfig};
 use libredox::{flag::O_RDONLY, Fd};
 use log::LevelFilter;
 use redox_log::{OutputBuilder, RedoxLogger};
@@ -12,9 +12,16 @@ use redox_users::{All, AllUsers, Config};
 
 /// contain_login:
 /// Login as user, with restricted access to files, directories and schemes.
-/// Logging in with uid==0 will not have any restrictions.
+/// Logging in with uid==0 will not have any restrictions,
+/// unless `contain_root = true` is set in the config.
 ///
-/// The list of files, directories and schemes is read from the CONTAIN_FILE.
+/// The list of files, directories and schemes is read from the CONTAIN_FILE,
+/// with any drop-in files in CONTAIN_DIR merged in.
+/// The user's config is then added from CONTAIN_DIR/groups/<group>.toml,
+/// CONTAIN_DIR/users/<name>.toml and the profiles named in the
+/// `[groups]` and `[users]` tables, see `select_config`.
+/// Paths in the CONTAIN_FILE may use ${HOME}, ${USER}, ${UID} and ${SCHEME},
+/// which are replaced with the values for the user logging in.
 /// The user's home directory is added as a writable directory.
 /// A thread is started to manage proxy schemes that do the filtering.
 /// Schemes that are allowed but not proxied are kept in the namespace.
@@ -24,8 +31,9 @@ use redox_users::{All, AllUsers, Config};
 /// The user's shell is forked and exec'd in the modified namespace.
 /// When a file is opened in one of the proxied schemes, it is checked against
 /// the list of files and directories that are permitted.
-/// Files that include O_RDWR or O_WRONLY flags are not permitted if they
-/// are listed as rofiles or rodirs in the CONTAIN_FILE.
+/// Files that are listed as rofiles or rodirs in the CONTAIN_FILE may only be
+/// opened O_RDONLY, without O_CREAT, O_TRUNC or O_APPEND,
+/// and may not be unlinked or removed.
 /// When the user's shell exits, the proxy schemes are shut down and
 /// the namespace is dropped.
 /// Note that there does not currently exist a means to delete the namespace
@@ -34,6 +42,7 @@ use redox_users::{All, AllUsers, Config};
 const ISSUE_FILE: &str = "/etc/issue";
 const MOTD_FILE: &str = "/etc/motd";
 const CONTAIN_FILE: &str = "/etc/contain.toml";
+const CONTAIN_DIR: &str = "/etc/contain.d";
 
 fn setup_logging(level: LevelFilter) -> Option<&'static RedoxLogger> {
     let mut logger = RedoxLogger::new().with_output(
@@ -180,15 +189,29 @@ fn main() -> Result<()> {
 
         let user = user.unwrap();
 
-        if user.uid == 0 {
-            let command = user.shell_cmd();
+        let config = ContainConfig::from_file(CONTAIN_FILE)
+            .and_then(|c| c.with_drop_ins(CONTAIN_DIR))
+            .and_then(|c| select_user_config(c, user));
 
-            let _ = run_not_contained(command);
-        } else {
-            if let Ok(mut config) = ContainConfig::from_file(CONTAIN_FILE) {
-                config.add_dir(&user.home);
-                let _ = run_contained(config, user.shell_cmd());
+        match config {
+            Ok(config) if !config.contains_uid(user.uid) => {
+                let _ = run_not_contained(user.shell_cmd());
             }
+            // Root can still log in without a config
+            Err(e) if user.uid == 0 => {
+                eprintln!("contain_login: {}: {}", CONTAIN_FILE, e);
+                let _ = run_not_contained(user.shell_cmd());
+            }
+            Ok(mut config) => {
+                let vars = ConfigVars::from_user(user);
+                if let Err(e) = config.expand_vars(&vars) {
+                    eprintln!("contain_login: {}: {}", CONTAIN_FILE, e);
+                } else {
+                    config.add_dir(&vars.home);
+                    let _ = run_contained(config, user.shell_cmd());
+                }
+            }
+            Err(e) => eprintln!("contain_login: {}: {}", CONTAIN_FILE, e),
         }
 
         stdout.write(b"\n")?;
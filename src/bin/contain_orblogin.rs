@@ -2,7 +2,7 @@ use log::{error, info};
 use std::process::Command;
 use std::{env, io, str};
 
-use contain::{run_contained, run_not_contained, ContainConfig};
+use contain::{run_contained, run_not_contained, select_user_config, ConfigVars, ContainConfig};
 use orbclient::{Color, EventOption, Renderer, Window, WindowFlag};
 use orbfont::Font;
 use orbimage::Image;
@@ -33,6 +33,7 @@ impl BackgroundMode {
 }
 
 const CONTAIN_FILE: &str = "/etc/contain.toml";
+const CONTAIN_DIR: &str = "/etc/contain.d";
 
 fn find_scale(
     image: &Image,
@@ -95,7 +96,7 @@ fn login_command(
     pass: &str,
     launcher_cmd: &str,
     launcher_args: &[String],
-) -> Option<(Command, usize, String)> {
+) -> Option<(Command, usize, ConfigVars, io::Result<ContainConfig>)> {
     let sys_users = match AllUsers::authenticator(Config::default()) {
         Ok(users) => users,
         // Not maybe the best thing to do...
@@ -110,7 +111,10 @@ fn login_command(
                     command.arg(&arg);
                 }
 
-                Some((command, user.uid, user.home.to_owned()))
+                let config = ContainConfig::from_file(CONTAIN_FILE)
+                    .and_then(|c| c.with_drop_ins(CONTAIN_DIR))
+                    .and_then(|c| select_user_config(c, user));
+                Some((command, user.uid, ConfigVars::from_user(user), config))
             } else {
                 None
             }
@@ -122,7 +126,7 @@ fn login_command(
 fn login_window(
     launcher_cmd: &str,
     launcher_args: &[String],
-) -> Result<Option<(Command, usize, String)>, String> {
+) -> Result<Option<(Command, usize, ConfigVars, io::Result<ContainConfig>)>, String> {
     let font = Font::find(Some("Sans"), None, None)?;
 
     let image_mode = BackgroundMode::from_str("zoom");
@@ -450,16 +454,25 @@ fn main() -> io::Result<()> {
 
     loop {
         match login_window(&launcher_cmd, &launcher_args) {
-            Ok(Some((command, uid, home))) => {
-                if uid == 0 {
+            Ok(Some((command, uid, vars, config))) => match config {
+                Ok(config) if !config.contains_uid(uid) => {
                     let _ = run_not_contained(command);
-                } else {
-                    if let Ok(mut config) = ContainConfig::from_file(CONTAIN_FILE) {
-                        config.add_dir(&home);
+                }
+                // Root can still log in without a config
+                Err(e) if uid == 0 => {
+                    error!("{}: {}", CONTAIN_FILE, e);
+                    let _ = run_not_contained(command);
+                }
+                Ok(mut config) => {
+                    if let Err(e) = config.expand_vars(&vars) {
+                        error!("{}: {}", CONTAIN_FILE, e);
+                    } else {
+                        config.add_dir(&vars.home);
                         let _ = run_contained(config, command);
                     }
                 }
-            }
+                Err(e) => error!("{}: {}", CONTAIN_FILE, e),
+            },
             Ok(None) => info!("login completed without a command"),
             Err(e) => error!("{}", e),
         }
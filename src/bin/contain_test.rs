@@ -1,12 +1,34 @@
-use contain::{run_contained, ContainConfig, CONTAIN_EXEC_FAIL_EXIT};
+use contain::{run_contained, ContainConfig, ContainError, ContainExit, Container};
 use log::{debug, error, info, LevelFilter};
 use redox_log::{OutputBuilder, RedoxLogger};
-use std::env;
-use std::process::Command;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{exit, Command, Stdio};
+use std::time::Duration;
+use std::{env, fs};
+use syscall::flag::{O_APPEND, O_CLOEXEC, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+
+const MATRIX_DIR: &str = "/tmp/contain_test";
+// The uid and gid of the default non-root user
+const USER_ID: u32 = 1000;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
+    // When run inside a container by test_flag_matrix
+    if args.len() == 4 && ["matrix", "unlink", "rmdir"].contains(&args[1].as_str()) {
+        let _ = RedoxLogger::new()
+            .with_output(
+                OutputBuilder::stdout()
+                    .with_filter(LevelFilter::Info)
+                    .with_ansi_escape_codes()
+                    .build(),
+            )
+            .with_process_name("contain_test_helper".into())
+            .enable();
+        exit(run_helper(&args[1], &args[2], args[3] == "rw"));
+    }
+
     let debug_level = match args.len() {
         1 => LevelFilter::Info,
         2 if args[1] == "-d" => LevelFilter::Debug,
@@ -31,7 +53,15 @@ fn main() {
 
     let mut succeeded = 0;
     let mut failed = 0;
-    let tests = [test_exec, test_pass_schemes, test_sandbox_schemes, test_read_only];
+    let tests = [
+        test_exec,
+        test_spawn,
+        test_pass_schemes,
+        test_sandbox_schemes,
+        test_read_only,
+        test_deny,
+        test_flag_matrix,
+    ];
     for test in tests {
         let (s, f) = test();
         succeeded += s;
@@ -46,17 +76,19 @@ fn test_exec() -> (u32, u32) {
     let mut failed = 0;
 
     // exec a command not in the config
-    let mut config = ContainConfig::default();
-    config.pass_schemes.push("thisproc".to_string());
-    config.pass_schemes.push("rand".to_string());
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .build()
+        .unwrap();
     let command = Command::new("file:/bin/ls");
     match run_contained(config, command) {
-        Ok(exit_code) if exit_code == CONTAIN_EXEC_FAIL_EXIT * 256 => {
-            debug!("empty scheme test succeeded: {:x}", exit_code);
+        Err(ContainError::ExecFailed(e)) => {
+            debug!("empty scheme test succeeded: {}", e);
             succeeded += 1;
         }
-        Ok(exit_code) => {
-            error!("empty scheme test failed: {:x}", exit_code);
+        Ok(status) => {
+            error!("empty scheme test failed: {}", status);
             failed += 1;
         }
         Err(e) => {
@@ -66,22 +98,24 @@ fn test_exec() -> (u32, u32) {
     }
 
     // exec a command in the config
-    let mut config = ContainConfig::default();
-    config.pass_schemes.push("thisproc".to_string());
-    config.pass_schemes.push("rand".to_string());
-    config.sandbox_schemes.push("file".to_string());
-    config.rofiles.push("file:/bin/cat".to_string());
-    config.rofiles.push("file:/bin/coreutils".to_string());
-    config.rofiles.push("file:/etc/passwd".to_string());
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/cat")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_file_ro("file:/etc/passwd")
+        .build()
+        .unwrap();
     let mut command = Command::new("file:/bin/cat");
     command.arg("file:/etc/passwd");
     match run_contained(config, command) {
-        Ok(exit_code) if exit_code == 0 => {
+        Ok(status) if status.success() => {
             debug!("exec test succeeded");
             succeeded += 1;
         }
-        Ok(exit_code) => {
-            error!("exec test failed, exit code: {:x}", exit_code);
+        Ok(status) => {
+            error!("exec test failed, {}", status);
             failed += 1;
         }
         Err(e) => {
@@ -89,6 +123,167 @@ fn test_exec() -> (u32, u32) {
             failed += 1;
         }
     }
+
+    // exec as a user, with the uid, gid and directory set like User::shell_cmd does,
+    // which only works if they are applied after entering the namespace
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/cat")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_dir_ro("file:/etc")
+        .build()
+        .unwrap();
+    let mut command = Command::new("file:/bin/cat");
+    command
+        .arg("passwd")
+        .uid(USER_ID)
+        .gid(USER_ID)
+        .current_dir("file:/etc");
+    match run_contained(config, command) {
+        Ok(status) if status.success() => {
+            debug!("exec as user test succeeded");
+            succeeded += 1;
+        }
+        Ok(status) => {
+            error!("exec as user test failed, {}", status);
+            failed += 1;
+        }
+        Err(e) => {
+            error!("exec as user test failed: {:?}", e);
+            failed += 1;
+        }
+    }
+    (succeeded, failed)
+}
+
+// A config that can run cat and sleep
+fn spawn_config() -> ContainConfig {
+    ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/cat")
+        .allow_file_ro("file:/bin/sleep")
+        .allow_file_ro("file:/bin/coreutils")
+        .build()
+        .unwrap()
+}
+
+// True if the process, or its process group, is gone
+fn gone(pid: u32) -> bool {
+    unsafe { libc::kill(-(pid as libc::pid_t), 0) == -1 }
+}
+
+fn test_spawn() -> (u32, u32) {
+    info!("test_spawn");
+    let mut succeeded = 0;
+    let mut failed = 0;
+
+    // write to the command's stdin and read its stdout
+    let mut command = Command::new("file:/bin/cat");
+    command.stdin(Stdio::piped()).stdout(Stdio::piped());
+    let result = Container::spawn(spawn_config(), command).and_then(|mut child| {
+        let mut stdin = child.stdin.take().unwrap();
+        let _ = stdin.write_all(b"contain_test\n");
+        drop(stdin);
+        let mut output = String::new();
+        let _ = child.stdout.take().unwrap().read_to_string(&mut output);
+        child.wait().map(|status| (status, output))
+    });
+    match result {
+        Ok((status, output)) if status.success() && output == "contain_test\n" => {
+            debug!("spawn piped test succeeded");
+            succeeded += 1;
+        }
+        Ok((status, output)) => {
+            error!("spawn piped test failed, {}, output {:?}", status, output);
+            failed += 1;
+        }
+        Err(e) => {
+            error!("spawn piped test failed: {:?}", e);
+            failed += 1;
+        }
+    }
+
+    // try_wait while the command runs, then kill it
+    let mut command = Command::new("file:/bin/sleep");
+    command.arg("30");
+    let result = Container::spawn(spawn_config(), command).and_then(|mut child| {
+        let running = child.try_wait()?.is_none();
+        child.kill(libc::SIGTERM)?;
+        child.wait().map(|status| (running, status))
+    });
+    match result {
+        Ok((true, status)) if status.signal == Some(libc::SIGTERM) => {
+            debug!("spawn try_wait and kill test succeeded");
+            succeeded += 1;
+        }
+        Ok((running, status)) => {
+            error!(
+                "spawn try_wait and kill test failed, running {}, {}",
+                running, status
+            );
+            failed += 1;
+        }
+        Err(e) => {
+            error!("spawn try_wait and kill test failed: {:?}", e);
+            failed += 1;
+        }
+    }
+
+    // the container is killed when the timeout runs out
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/sleep")
+        .allow_file_ro("file:/bin/coreutils")
+        .timeout(Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let mut command = Command::new("file:/bin/sleep");
+    command.arg("30");
+    match Container::spawn(config, command).and_then(|mut child| child.wait()) {
+        Err(ContainError::TimedOut) => {
+            debug!("spawn timeout test succeeded");
+            succeeded += 1;
+        }
+        Ok(status) => {
+            error!("spawn timeout test failed, {}", status);
+            failed += 1;
+        }
+        Err(e) => {
+            error!("spawn timeout test failed: {:?}", e);
+            failed += 1;
+        }
+    }
+
+    // dropping the child kills the container
+    let mut command = Command::new("file:/bin/sleep");
+    command.arg("30");
+    match Container::spawn(spawn_config(), command) {
+        Ok(child) => {
+            let pid = child.id();
+            let shared_config = child.contain_thread().shared_config();
+            drop(child);
+            if !gone(pid) {
+                error!("spawn drop test failed, {} is still running", pid);
+                failed += 1;
+            } else if shared_config.upgrade().is_some() {
+                error!("spawn drop test failed, the scheme thread is still running");
+                failed += 1;
+            } else {
+                debug!("spawn drop test succeeded");
+                succeeded += 1;
+            }
+        }
+        Err(e) => {
+            error!("spawn drop test failed: {:?}", e);
+            failed += 1;
+        }
+    }
     (succeeded, failed)
 }
 
@@ -107,12 +302,12 @@ fn test_pass_schemes() -> (u32, u32) {
     // let mut command = Command::new("file:/bin/cat");
     // command.arg("null:");
     // match run_contained(config, command) {
-    //     Ok(exit_code) if exit_code == 1 * 256 => {
+    //     Ok(status) if status.code == Some(1) => {
     //         debug!("pass schemes test succeeded");
     //         succeeded += 1;
     //     }
-    //     Ok(exit_code) => {
-    //         error!("pass schemes test failed, exit code: {:x}", exit_code);
+    //     Ok(status) => {
+    //         error!("pass schemes test failed, {}", status);
     //         failed += 1;
     //     }
     //     Err(e) => {
@@ -122,22 +317,24 @@ fn test_pass_schemes() -> (u32, u32) {
     // }
 
     // read from a scheme we do include
-    let mut config = ContainConfig::default();
-    config.pass_schemes.push("thisproc".to_string());
-    config.pass_schemes.push("rand".to_string());
-    config.pass_schemes.push("null".to_string());
-    config.sandbox_schemes.push("file".to_string());
-    config.rofiles.push("file:/bin/cat".to_string());
-    config.rofiles.push("file:/bin/coreutils".to_string());
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .pass_scheme("null")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/cat")
+        .allow_file_ro("file:/bin/coreutils")
+        .build()
+        .unwrap();
     let mut command = Command::new("file:/bin/cat");
     command.arg("null:");
     match run_contained(config, command) {
-        Ok(exit_code) if exit_code == 0 => {
+        Ok(status) if status.success() => {
             debug!("pass schemes test succeeded");
             succeeded += 1;
         }
-        Ok(exit_code) => {
-            error!("pass schemes test failed, exit code: {:x}", exit_code);
+        Ok(status) => {
+            error!("pass schemes test failed, {}", status);
             failed += 1;
         }
         Err(e) => {
@@ -154,24 +351,26 @@ fn test_sandbox_schemes() -> (u32, u32) {
     let mut failed = 0;
 
     // try to access something not in the sandbox
-    let mut config = ContainConfig::default();
-    config.pass_schemes.push("thisproc".to_string());
-    config.pass_schemes.push("rand".to_string());
-    config.pass_schemes.push("null".to_string());
-    config.sandbox_schemes.push("file".to_string());
-    config.rofiles.push("file:/bin/dd".to_string());
-    config.rofiles.push("file:/bin/coreutils".to_string());
-    config.rofiles.push("file:/etc/passwd".to_string());
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .pass_scheme("null")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/dd")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_file_ro("file:/etc/passwd")
+        .build()
+        .unwrap();
     let mut command = Command::new("file:/bin/dd");
     command.arg("if=file:/etc/passwd");
     command.arg("of=file:/tmp/passwd1");
     match run_contained(config, command) {
-        Ok(exit_code) if exit_code == 1 * 256 => {
+        Ok(status) if status.code == Some(1) => {
             debug!("sandbox schemes test succeeded");
             succeeded += 1;
         }
-        Ok(exit_code) => {
-            error!("sandbox schemes test failed, exit code: {:x}", exit_code);
+        Ok(status) => {
+            error!("sandbox schemes test failed, {}", status);
             failed += 1;
         }
         Err(e) => {
@@ -181,25 +380,27 @@ fn test_sandbox_schemes() -> (u32, u32) {
     }
 
     // try to access something in the sandbox
-    let mut config = ContainConfig::default();
-    config.pass_schemes.push("thisproc".to_string());
-    config.pass_schemes.push("rand".to_string());
-    config.pass_schemes.push("null".to_string());
-    config.sandbox_schemes.push("file".to_string());
-    config.rofiles.push("file:/bin/dd".to_string());
-    config.rofiles.push("file:/bin/coreutils".to_string());
-    config.rofiles.push("file:/etc/passwd".to_string());
-    config.dirs.push("file:/tmp".to_string());
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .pass_scheme("null")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/dd")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_file_ro("file:/etc/passwd")
+        .allow_dir("file:/tmp")
+        .build()
+        .unwrap();
     let mut command = Command::new("file:/bin/dd");
     command.arg("if=file:/etc/passwd");
     command.arg("of=file:/tmp/passwd1");
     match run_contained(config, command) {
-        Ok(exit_code) if exit_code == 0 => {
+        Ok(status) if status.success() => {
             debug!("sandbox schemes test succeeded");
             succeeded += 1;
         }
-        Ok(exit_code) => {
-            error!("sandbox schemes test failed, exit code: {:x}", exit_code);
+        Ok(status) => {
+            error!("sandbox schemes test failed, {}", status);
             failed += 1;
         }
         Err(e) => {
@@ -216,25 +417,27 @@ fn test_read_only() -> (u32, u32) {
     let mut failed = 0;
 
     // try to write to something that is read only
-    let mut config = ContainConfig::default();
-    config.pass_schemes.push("thisproc".to_string());
-    config.pass_schemes.push("rand".to_string());
-    config.pass_schemes.push("null".to_string());
-    config.sandbox_schemes.push("file".to_string());
-    config.rofiles.push("file:/bin/dd".to_string());
-    config.rofiles.push("file:/bin/coreutils".to_string());
-    config.rofiles.push("file:/etc/passwd".to_string());
-    config.rofiles.push("file:/tmp/passwd2".to_string());
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .pass_scheme("null")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/dd")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_file_ro("file:/etc/passwd")
+        .allow_file_ro("file:/tmp/passwd2")
+        .build()
+        .unwrap();
     let mut command = Command::new("file:/bin/dd");
     command.arg("if=file:/etc/passwd");
     command.arg("of=file:/tmp/passwd2");
     match run_contained(config, command) {
-        Ok(exit_code) if exit_code == 1 * 256 => {
+        Ok(status) if status.code == Some(1) => {
             debug!("read only test succeeded");
             succeeded += 1;
         }
-        Ok(exit_code) => {
-            error!("read only test failed, exit code: {:x}", exit_code);
+        Ok(status) => {
+            error!("read only test failed, {}", status);
             failed += 1;
         }
         Err(e) => {
@@ -244,26 +447,28 @@ fn test_read_only() -> (u32, u32) {
     }
 
     // try to access something in the sandbox
-    let mut config = ContainConfig::default();
-    config.pass_schemes.push("thisproc".to_string());
-    config.pass_schemes.push("rand".to_string());
-    config.pass_schemes.push("null".to_string());
-    config.sandbox_schemes.push("file".to_string());
-    config.rofiles.push("file:/bin/dd".to_string());
-    config.rofiles.push("file:/bin/coreutils".to_string());
-    config.rofiles.push("file:/etc/passwd".to_string());
-    config.files.push("file:/tmp".to_string());
-    config.files.push("file:/tmp/passwd2".to_string());
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .pass_scheme("null")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/dd")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_file_ro("file:/etc/passwd")
+        .allow_file("file:/tmp")
+        .allow_file("file:/tmp/passwd2")
+        .build()
+        .unwrap();
     let mut command = Command::new("file:/bin/dd");
     command.arg("if=file:/etc/passwd");
     command.arg("of=file:/tmp/passwd2");
     match run_contained(config, command) {
-        Ok(exit_code) if exit_code == 0 => {
+        Ok(status) if status.success() => {
             debug!("read only test succeeded");
             succeeded += 1;
         }
-        Ok(exit_code) => {
-            error!("read only test failed, exit code: {:x}", exit_code);
+        Ok(status) => {
+            error!("read only test failed, {}", status);
             failed += 1;
         }
         Err(e) => {
@@ -272,4 +477,236 @@ fn test_read_only() -> (u32, u32) {
         }
     }
     (succeeded, failed)
-}
\ No newline at end of file
+}
+
+fn test_deny() -> (u32, u32) {
+    info!("test_deny");
+    let mut succeeded = 0;
+    let mut failed = 0;
+
+    // try to write to something denied inside a writable directory
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .pass_scheme("null")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/dd")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_file_ro("file:/etc/passwd")
+        .allow_dir("file:/tmp")
+        .deny_dir("file:/tmp/denied")
+        .build()
+        .unwrap();
+    let _ = fs::create_dir_all("/tmp/denied");
+    let mut command = Command::new("file:/bin/dd");
+    command.arg("if=file:/etc/passwd");
+    command.arg("of=file:/tmp/denied/passwd3");
+    match run_contained(config, command) {
+        Ok(status) if status.code == Some(1) => {
+            debug!("deny test succeeded");
+            succeeded += 1;
+        }
+        Ok(status) => {
+            error!("deny test failed, {}", status);
+            failed += 1;
+        }
+        Err(e) => {
+            error!("deny test failed: {:?}", e);
+            failed += 1;
+        }
+    }
+
+    // try to read something denied that is also read only
+    let config = ContainConfig::builder()
+        .pass_scheme("thisproc")
+        .pass_scheme("rand")
+        .pass_scheme("null")
+        .sandbox_scheme("file")
+        .allow_file_ro("file:/bin/cat")
+        .allow_file_ro("file:/bin/coreutils")
+        .allow_file_ro("file:/etc/passwd")
+        .deny_file("file:/etc/passwd")
+        .build()
+        .unwrap();
+    let mut command = Command::new("file:/bin/cat");
+    command.arg("file:/etc/passwd");
+    match run_contained(config, command) {
+        Ok(status) if status.code == Some(1) => {
+            debug!("deny test succeeded");
+            succeeded += 1;
+        }
+        Ok(status) => {
+            error!("deny test failed, {}", status);
+            failed += 1;
+        }
+        Err(e) => {
+            error!("deny test failed: {:?}", e);
+            failed += 1;
+        }
+    }
+    (succeeded, failed)
+}
+
+// Every combination of access mode and O_CREAT, O_TRUNC and O_APPEND.
+fn open_flag_matrix() -> Vec<usize> {
+    let mut matrix = Vec::new();
+    for mode in [O_RDONLY, O_WRONLY, O_RDWR] {
+        for extra in 0..8 {
+            let mut flags = mode;
+            if extra & 1 != 0 {
+                flags |= O_CREAT;
+            }
+            if extra & 2 != 0 {
+                flags |= O_TRUNC;
+            }
+            if extra & 4 != 0 {
+                flags |= O_APPEND;
+            }
+            matrix.push(flags);
+        }
+    }
+    matrix
+}
+
+// Runs inside the container. Try the operation on the path and compare
+// the result to what is expected. The exit code is the number of mismatches.
+fn run_helper(op: &str, path: &str, writable: bool) -> i32 {
+    let mut mismatches = 0;
+    match op {
+        "matrix" => {
+            for flags in open_flag_matrix() {
+                let expected = writable || flags == O_RDONLY;
+                let res = libredox::call::open(path, (flags | O_CLOEXEC) as i32, 0o644);
+                if let Ok(fd) = res {
+                    let _ = libredox::call::close(fd);
+                }
+                if res.is_ok() == expected {
+                    debug!("open({}, {:X}) allowed: {}", path, flags, expected);
+                } else {
+                    error!(
+                        "open({}, {:X}) expected allowed: {}, got {:?}",
+                        path, flags, expected, res
+                    );
+                    mismatches += 1;
+                }
+            }
+        }
+        "unlink" | "rmdir" => {
+            let res = if op == "unlink" {
+                syscall::unlink(path)
+            } else {
+                syscall::rmdir(path)
+            };
+            if res.is_ok() != writable {
+                error!(
+                    "{}({}) expected allowed: {}, got {:?}",
+                    op, path, writable, res
+                );
+                mismatches += 1;
+            }
+        }
+        _ => unreachable!(),
+    }
+    mismatches
+}
+
+fn test_flag_matrix() -> (u32, u32) {
+    info!("test_flag_matrix");
+    let mut succeeded = 0;
+    let mut failed = 0;
+
+    let exe = match env::current_exe() {
+        Ok(exe) => exe.to_string_lossy().to_string(),
+        Err(e) => {
+            error!("flag matrix test could not get current exe: {}", e);
+            return (0, 1);
+        }
+    };
+    let exe = if exe.contains(':') {
+        exe
+    } else {
+        format!("file:{}", exe)
+    };
+
+    let _ = fs::remove_dir_all(MATRIX_DIR);
+    for dir in ["ro/dir", "rw/dir", "rodir/dir", "dir/dir"] {
+        if let Err(e) = fs::create_dir_all(format!("{}/{}", MATRIX_DIR, dir)) {
+            error!("flag matrix test could not create {}: {}", dir, e);
+            return (0, 1);
+        }
+    }
+    for file in ["ro/file", "rw/file", "rodir/file", "dir/file"] {
+        if let Err(e) = fs::write(format!("{}/{}", MATRIX_DIR, file), "contain_test\n") {
+            error!("flag matrix test could not create {}: {}", file, e);
+            return (0, 1);
+        }
+    }
+
+    // (helper operation, path, writable)
+    let cases = [
+        ("matrix", "ro/file", false),
+        ("matrix", "rw/file", true),
+        ("matrix", "rodir/file", false),
+        ("matrix", "dir/file", true),
+        ("unlink", "ro/file", false),
+        ("unlink", "rodir/file", false),
+        ("rmdir", "ro/dir", false),
+        ("rmdir", "rodir/dir", false),
+        ("unlink", "rw/file", true),
+        ("unlink", "dir/file", true),
+        ("rmdir", "rw/dir", true),
+        ("rmdir", "dir/dir", true),
+    ];
+    for (op, path, writable) in cases {
+        let config = ContainConfig::builder()
+            .pass_scheme("thisproc")
+            .pass_scheme("rand")
+            .sandbox_scheme("file")
+            .allow_file_ro(&exe)
+            .allow_file_ro(&format!("file:{}/ro/file", MATRIX_DIR))
+            .allow_file_ro(&format!("file:{}/ro/dir", MATRIX_DIR))
+            .allow_file(&format!("file:{}/rw/file", MATRIX_DIR))
+            .allow_file(&format!("file:{}/rw/dir", MATRIX_DIR))
+            .allow_dir_ro(&format!("file:{}/rodir", MATRIX_DIR))
+            .allow_dir(&format!("file:{}/dir", MATRIX_DIR))
+            .build()
+            .unwrap();
+        let mut command = Command::new(&exe);
+        command.arg(op);
+        command.arg(format!("file:{}/{}", MATRIX_DIR, path));
+        command.arg(if writable { "rw" } else { "ro" });
+        let tries = if op == "matrix" {
+            open_flag_matrix().len() as u32
+        } else {
+            1
+        };
+        match run_contained(config, command) {
+            Ok(ContainExit {
+                code: Some(mismatches),
+                ..
+            }) => {
+                let mismatches = mismatches as u32;
+                if mismatches == 0 {
+                    debug!("flag matrix test {} {} succeeded", op, path);
+                } else {
+                    error!(
+                        "flag matrix test {} {} failed {} of {}",
+                        op, path, mismatches, tries
+                    );
+                }
+                succeeded += tries - mismatches.min(tries);
+                failed += mismatches.min(tries);
+            }
+            Ok(status) => {
+                error!("flag matrix test {} {} failed, {}", op, path, status);
+                failed += tries;
+            }
+            Err(e) => {
+                error!("flag matrix test {} {} failed: {:?}", op, path, e);
+                failed += tries;
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(MATRIX_DIR);
+    (succeeded, failed)
+}
@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use log::debug;
+
+use crate::diagnostic::{ConfigDiagnostic, DiagnosticKind};
+use crate::rule::Rule;
+use crate::runner::OnExit;
+use crate::ContainConfig;
+
+/// Builds a `ContainConfig` in code, see `ContainConfig::builder`.
+/// Scheme names and paths are checked as they are added,
+/// and `build` returns every problem that was found.
+/// Whether the schemes exist is only checked when the config is run.
+#[derive(Clone, Debug, Default)]
+pub struct ContainConfigBuilder {
+    config: ContainConfig,
+    diagnostics: Vec<ConfigDiagnostic>,
+}
+
+// A scheme name, without ':' or '/'
+fn is_scheme(scheme: &str) -> bool {
+    !scheme.is_empty() && !scheme.contains([':', '/'])
+}
+
+// A path in the form "scheme:/path"
+fn is_path(path: &str) -> bool {
+    path.split_once(':')
+        .is_some_and(|(scheme, rest)| is_scheme(scheme) && rest.starts_with('/'))
+}
+
+impl ContainConfigBuilder {
+    // Record a problem with an entry, which is still added so `build` can report the rest
+    fn check(&mut self, ok: bool, kind: DiagnosticKind, entry: &str) {
+        if !ok {
+            debug!("{}: {}", entry, kind);
+            self.diagnostics
+                .push(ConfigDiagnostic::new(kind, entry, &self.config));
+        }
+    }
+
+    fn check_scheme(&mut self, scheme: &str) {
+        self.check(is_scheme(scheme), DiagnosticKind::InvalidScheme, scheme);
+    }
+
+    fn check_path(&mut self, path: &str) {
+        self.check(is_path(path), DiagnosticKind::NotAPath, path);
+    }
+
+    /// A scheme that is passed through to the container, e.g. "rand"
+    pub fn pass_scheme(mut self, scheme: &str) -> Self {
+        self.check_scheme(scheme);
+        self.config.pass_schemes.push(scheme.to_string());
+        self
+    }
+
+    /// A scheme that is sandboxed by contain, e.g. "file"
+    pub fn sandbox_scheme(mut self, scheme: &str) -> Self {
+        self.check_scheme(scheme);
+        self.config.sandbox_schemes.push(scheme.to_string());
+        self
+    }
+
+    pub fn allow_file(mut self, path: &str) -> Self {
+        self.check_path(path);
+        self.config.add_file(path);
+        self
+    }
+
+    pub fn allow_file_ro(mut self, path: &str) -> Self {
+        self.check_path(path);
+        self.config.add_rofile(path);
+        self
+    }
+
+    pub fn allow_dir(mut self, path: &str) -> Self {
+        self.check_path(path);
+        self.config.add_dir(path);
+        self
+    }
+
+    pub fn allow_dir_ro(mut self, path: &str) -> Self {
+        self.check_path(path);
+        self.config.add_rodir(path);
+        self
+    }
+
+    pub fn deny_file(mut self, path: &str) -> Self {
+        self.check_path(path);
+        self.config.add_deny_file(path);
+        self
+    }
+
+    pub fn deny_dir(mut self, path: &str) -> Self {
+        self.check_path(path);
+        self.config.add_deny_dir(path);
+        self
+    }
+
+    /// A file or directory with an explicit set of permitted operations
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.check_path(&rule.path);
+        self.config.add_rule(rule);
+        self
+    }
+
+    /// The root directory, which must be in a sandboxed scheme
+    pub fn chroot(mut self, root: &str) -> Self {
+        self.check_path(root);
+        self.config.add_chroot(root);
+        self
+    }
+
+    /// Pass on an environment variable, or the ones matching a pattern like "LC_*"
+    pub fn env_allow(mut self, name: &str) -> Self {
+        self.config.env.allow.push(name.to_string());
+        self
+    }
+
+    /// Set an environment variable for the command
+    pub fn env_set(mut self, name: &str, value: &str) -> Self {
+        self.config.env.unset.retain(|unset| unset != name);
+        self.config
+            .env
+            .set
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn on_exit(mut self, on_exit: OnExit) -> Self {
+        self.config.on_exit = Some(on_exit);
+        self
+    }
+
+    /// How long the command may run, rounded up to whole seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+        self.config.timeout = Some(secs);
+        self
+    }
+
+    /// Make any entry that validation would drop an error when the config is run
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = Some(strict);
+        self
+    }
+
+    /// The config, or every problem found in it.
+    /// Besides the checks made while adding entries, each path and the root
+    /// must be in a sandboxed scheme, a scheme can't be both passed and sandboxed,
+    /// and glob patterns must be valid.
+    pub fn build(mut self) -> Result<ContainConfig, Vec<ConfigDiagnostic>> {
+        let config = &self.config;
+        let mut diagnostics = std::mem::take(&mut self.diagnostics);
+        for scheme in config.pass_schemes.iter() {
+            if config.sandbox_schemes.contains(scheme) {
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::PassedAndSandboxed,
+                    scheme,
+                    config,
+                ));
+            }
+        }
+        let paths = config
+            .files
+            .iter()
+            .chain(config.dirs.iter())
+            .chain(config.rofiles.iter())
+            .chain(config.rodirs.iter())
+            .chain(config.deny_files.iter())
+            .chain(config.deny_dirs.iter())
+            .chain(config.rules.iter().map(|rule| &rule.path))
+            .chain(config.root.iter());
+        for path in paths {
+            if is_path(path) && !config.in_sandbox(path) {
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::NotSandboxed,
+                    path,
+                    config,
+                ));
+            }
+        }
+        if diagnostics.is_empty() {
+            let patterns = [
+                self.config.rule_set().err(),
+                self.config.env.allow_list().err(),
+            ];
+            for e in patterns.into_iter().flatten() {
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::InvalidPattern,
+                    &e.pattern,
+                    &self.config,
+                ));
+            }
+        }
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::Operation;
+
+    #[test]
+    fn builds_a_valid_config() {
+        let config = ContainConfig::builder()
+            .pass_scheme("rand")
+            .sandbox_scheme("file")
+            .allow_file("file:/dev/null")
+            .allow_file_ro("file:/etc/passwd")
+            .allow_dir("file:/tmp")
+            .allow_dir_ro("file:/bin")
+            .deny_file("file:/tmp/secret")
+            .rule(Rule::dir("file:/var/log/*.log", Operation::READ_ONLY))
+            .chroot("file:/jail")
+            .timeout(Duration::from_millis(1500))
+            .build()
+            .unwrap();
+        assert_eq!(config.pass_schemes, ["rand"]);
+        assert_eq!(config.files, ["file:/dev/null"]);
+        assert_eq!(config.rofiles, ["file:/etc/passwd"]);
+        assert_eq!(config.rodirs, ["file:/bin"]);
+        assert_eq!(config.root.as_deref(), Some("file:/jail"));
+        assert_eq!(config.timeout, Some(2));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let errors = ContainConfig::builder()
+            .pass_scheme("rand:")
+            .pass_scheme("file")
+            .sandbox_scheme("file")
+            .allow_dir("file:tmp")
+            .allow_file_ro("null:/x")
+            .chroot("/jail")
+            .build()
+            .unwrap_err();
+        let found: Vec<(DiagnosticKind, &str)> =
+            errors.iter().map(|d| (d.kind, d.entry.as_str())).collect();
+        assert_eq!(
+            found,
+            [
+                (DiagnosticKind::InvalidScheme, "rand:"),
+                (DiagnosticKind::NotAPath, "file:tmp"),
+                (DiagnosticKind::NotAPath, "/jail"),
+                (DiagnosticKind::PassedAndSandboxed, "file"),
+                (DiagnosticKind::NotSandboxed, "null:/x"),
+            ]
+        );
+
+        let errors = ContainConfig::builder()
+            .sandbox_scheme("file")
+            .allow_dir("file:/tmp/[a")
+            .build()
+            .unwrap_err();
+        assert_eq!(errors[0].kind, DiagnosticKind::InvalidPattern);
+
+        let errors = ContainConfig::builder()
+            .env_allow("LC_[")
+            .build()
+            .unwrap_err();
+        assert_eq!(errors[0].kind, DiagnosticKind::InvalidPattern);
+        assert_eq!(errors[0].entry, "LC_[");
+    }
+}
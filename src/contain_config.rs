@@ -1,13 +1,88 @@
 use std::{
-    fs::{self, File},
-    io::Error,
-    path::Path,
+    collections::BTreeMap,
+    fmt, fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
 };
 
 use log::{debug, error};
 use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::config_builder::ContainConfigBuilder;
+use crate::env::EnvConfig;
+use crate::glob::PatternError;
+use crate::profile::ProfileInfo;
+use crate::rule::{path_in_scheme, Operation, Rule, RuleSet};
+use crate::runner::OnExit;
+use crate::{ContainError, ContainResult};
+
+/// Values for the variables that may be used in config paths,
+/// e.g. "file:/home/${USER}/.cache" or "file:/tmp/${UID}".
+/// The default has no user, so every variable in it is unknown.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigVars {
+    /// `${HOME}`, the user's home directory, including the scheme
+    pub home: String,
+    /// `${USER}`, the user's login name
+    pub user: String,
+    /// `${UID}`, the user's id
+    pub uid: usize,
+    /// `${SCHEME}`, the scheme of the user's home directory
+    pub scheme: String,
+}
+
+impl ConfigVars {
+    pub fn from_user<A>(user: &redox_users::User<A>) -> Self {
+        let home = match user.home.split_once(':') {
+            Some((scheme, _)) if !scheme.contains('/') => user.home.clone(),
+            _ => format!("file:/{}", user.home.trim_start_matches('/')),
+        };
+        let scheme = home.split_once(':').unwrap().0.to_string();
+        Self {
+            home,
+            user: user.user.clone(),
+            uid: user.uid,
+            scheme,
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        if self.user.is_empty() {
+            return None;
+        }
+        match name {
+            "HOME" => Some(self.home.clone()),
+            "USER" => Some(self.user.clone()),
+            "UID" => Some(self.uid.to_string()),
+            "SCHEME" => Some(self.scheme.clone()),
+            _ => None,
+        }
+    }
+
+    /// Replace each `${NAME}` in the string with its value.
+    pub fn expand(&self, s: &str) -> ContainResult<String> {
+        let mut expanded = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            let name_len = rest[start + 2..].find('}').ok_or_else(|| {
+                ContainError::UnknownVariable(format!("unterminated variable in {}", s))
+            })?;
+            let name = &rest[start + 2..start + 2 + name_len];
+            let value = self.get(name).ok_or_else(|| {
+                ContainError::UnknownVariable(format!("unknown variable ${{{}}} in {}", name, s))
+            })?;
+            expanded.push_str(&value);
+            rest = &rest[start + 2 + name_len + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct ContainConfig {
     /// Optional root directory for chroot
     /// Not normally specified in the config file
@@ -24,6 +99,138 @@ pub struct ContainConfig {
     pub rofiles: Vec<String>,
     /// directories with readonly contents
     pub rodirs: Vec<String>,
+    /// files and directories with an explicit set of permitted operations
+    pub rules: Vec<Rule>,
+    /// exact match files to deny, overriding any allow
+    pub deny_files: Vec<String>,
+    /// directories to deny, overriding any allow
+    pub deny_dirs: Vec<String>,
+    /// other config files to merge, relative to this file
+    pub include: Vec<String>,
+    /// the environment of the command, see `EnvConfig`
+    #[serde(skip_serializing_if = "EnvConfig::is_empty")]
+    pub env: EnvConfig,
+    /// what to do with processes left in the container when the command exits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_exit: Option<OnExit>,
+    /// seconds the command may run before the container is killed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// make every entry that validation would drop an error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+    /// contain root (uid 0) when logging in, see `select_user_config`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contain_root: Option<bool>,
+    /// name and description, if this config is a named profile
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileInfo>,
+    /// profiles to add for each user name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub users: BTreeMap<String, Vec<String>>,
+    /// profiles to add for each group name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// where each entry in the lists and the root was read from, in the order they were read.
+    /// The entries in `rules` have their own source.
+    #[serde(skip)]
+    pub sources: Vec<EntrySource>,
+}
+
+/// The keys of a config file, for checking for unknown keys
+pub(crate) const CONFIG_KEYS: &[&str] = &[
+    "root",
+    "pass_schemes",
+    "sandbox_schemes",
+    "files",
+    "dirs",
+    "rofiles",
+    "rodirs",
+    "rules",
+    "deny_files",
+    "deny_dirs",
+    "include",
+    "env",
+    "on_exit",
+    "timeout",
+    "strict",
+    "contain_root",
+    "profile",
+    "users",
+    "groups",
+];
+/// The keys of each entry in `rules`
+pub(crate) const RULE_KEYS: &[&str] = &["path", "kind", "allow"];
+/// The keys of the `[profile]` table
+pub(crate) const PROFILE_KEYS: &[&str] = &["name", "description"];
+
+/// The file and line a config entry was read from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    pub file: String,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.file, line),
+            None => write!(f, "{}", self.file),
+        }
+    }
+}
+
+/// Where an entry in one of the lists of a config, or the root, was read from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntrySource {
+    /// the key of the list, e.g. "dirs"
+    pub key: &'static str,
+    pub entry: String,
+    pub source: Source,
+}
+
+// The position of each entry in a TOML config, read along with the config
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct TomlSpans {
+    root: Option<Spanned<String>>,
+    pass_schemes: Vec<Spanned<String>>,
+    sandbox_schemes: Vec<Spanned<String>>,
+    files: Vec<Spanned<String>>,
+    dirs: Vec<Spanned<String>>,
+    rofiles: Vec<Spanned<String>>,
+    rodirs: Vec<Spanned<String>>,
+    deny_files: Vec<Spanned<String>>,
+    deny_dirs: Vec<Spanned<String>>,
+    rules: Vec<RuleSpan>,
+}
+
+#[derive(Deserialize)]
+struct RuleSpan {
+    path: Spanned<String>,
+}
+
+impl TomlSpans {
+    fn lists(&self) -> [(&'static str, &Vec<Spanned<String>>); 8] {
+        [
+            ("pass_schemes", &self.pass_schemes),
+            ("sandbox_schemes", &self.sandbox_schemes),
+            ("files", &self.files),
+            ("dirs", &self.dirs),
+            ("rofiles", &self.rofiles),
+            ("rodirs", &self.rodirs),
+            ("deny_files", &self.deny_files),
+            ("deny_dirs", &self.deny_dirs),
+        ]
+    }
+}
+
+// The line that a byte offset in the file is on
+fn line_of(config_str: &str, offset: usize) -> usize {
+    config_str[..offset.min(config_str.len())]
+        .matches('\n')
+        .count()
+        + 1
 }
 
 impl ContainConfig {
@@ -45,26 +252,121 @@ impl ContainConfig {
             dirs: to_string_vec(&["file:/bin"]),
             rofiles: to_string_vec(&["file:/etc/passwd", "file:/etc/hostname", "file:/tmp"]),
             rodirs: to_string_vec(&["file:/bin"]),
+            rules: vec![],
+            deny_files: vec![],
+            deny_dirs: vec![],
+            env: EnvConfig {
+                allow: to_string_vec(&["PATH", "TERM", "COLUMNS", "LINES", "LANG", "TZ"]),
+                ..Default::default()
+            },
+            ..Default::default()
         }
     }
 
-    /// Deserialize the config from a file
+    /// Deserialize the config from a file, and merge the files it includes.
+    /// Fails if a pattern in the `[env]` allow list is invalid.
     pub fn from_file(filename: &str) -> Result<Self, Error> {
+        let config = Self::from_file_including(filename, &mut Vec::new())?;
+        if let Err(e) = config.env.allow_list() {
+            error!("Contain: {}: env: {}", filename, e);
+            return Err(Error::other(format!("{}: env: {}", filename, e)));
+        }
+        Ok(config)
+    }
+
+    // Read a config file and its includes, with the chain of including files
+    // in `including` so a cycle can be detected.
+    fn from_file_including(filename: &str, including: &mut Vec<PathBuf>) -> Result<Self, Error> {
+        let canon_file = Path::new(filename)
+            .canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(filename));
+        if including.contains(&canon_file) {
+            let mut chain: Vec<String> = including
+                .iter()
+                .map(|f| f.to_string_lossy().to_string())
+                .collect();
+            chain.push(canon_file.to_string_lossy().to_string());
+            error!("Contain: include cycle: {}", chain.join(" -> "));
+            return Err(Error::other(format!(
+                "include cycle: {}",
+                chain.join(" -> ")
+            )));
+        }
+        including.push(canon_file);
+
+        let mut config = Self::read_file(filename)?;
+        let includes = std::mem::take(&mut config.include);
+        let mut merged = ContainConfig::default();
+        for include in includes {
+            // relative includes are relative to the including file
+            let include_file = match Path::new(filename).parent() {
+                Some(dir) if Path::new(&include).is_relative() => {
+                    dir.join(&include).to_string_lossy().to_string()
+                }
+                _ => include,
+            };
+            debug!("{} includes {}", filename, include_file);
+            merged.merge(Self::from_file_including(&include_file, including)?);
+        }
+        merged.merge(config);
+
+        including.pop();
+        Ok(merged)
+    }
+
+    /// Serialize the config as TOML
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string(self).map_err(|e| {
+            error!("Contain: serializing config as .toml, {}", e);
+            Error::other(format!("serializing config as .toml, {}", e))
+        })
+    }
+
+    /// Serialize the config as RON
+    pub fn to_ron(&self) -> Result<String, Error> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|e| {
+            error!("Contain: serializing config as .ron, {}", e);
+            Error::other(format!("serializing config as .ron, {}", e))
+        })
+    }
+
+    /// Write the config to a file, as TOML or RON depending on the extension
+    pub fn to_file(&self, filename: &str) -> Result<(), Error> {
+        let config_str = match Path::new(filename).extension() {
+            Some(ext) if ext == "ron" => self.to_ron()?,
+            Some(ext) if ext == "toml" => self.to_toml()?,
+            Some(_) | None => {
+                error!("config filename must end in .toml or .ron");
+                return Err(Error::other(format!(
+                    "filename must end in .toml or .ron: {}",
+                    filename
+                )));
+            }
+        };
+        fs::write(filename, config_str).map_err(|e| {
+            error!("Contain: could not write config file {}, {}", filename, e);
+            e
+        })
+    }
+
+    // Deserialize a single file, and record where each entry came from
+    fn read_file(filename: &str) -> Result<Self, Error> {
         let config_file = Path::new(filename);
-        let config: ContainConfig = match config_file.extension() {
+        let (mut config, config_str): (ContainConfig, String) = match config_file.extension() {
             Some(ext) if ext == "ron" => {
                 debug!("reading .ron config from {:?}", config_file);
-                let config_fd = File::open(config_file).map_err(|e| {
+                let config_str = fs::read_to_string(config_file).map_err(|e| {
                     error!(
                         "Contain: could not open .ron config file {:?}, {}",
                         config_file, e
                     );
                     e
                 })?;
-                ron::de::from_reader(config_fd).map_err(|e| {
+                let config = ron::de::from_str(&config_str).map_err(|e| {
                     error!("Contain: serializing .ron config, {}: {}", filename, e);
                     Error::other(format!("{}", e))
-                })?
+                })?;
+                (config, config_str)
             }
             Some(ext) if ext == "toml" => {
                 debug!("reading .toml config from {:?}", config_file);
@@ -75,10 +377,11 @@ impl ContainConfig {
                     );
                     e
                 })?;
-                toml::from_str(&config_str).map_err(|e| {
+                let config = toml::from_str(&config_str).map_err(|e| {
                     error!("serializing failed, {}: {}", filename, e);
                     Error::other(format!("serializing failed, {}: {}", filename, e))
-                })?
+                })?;
+                (config, config_str)
             }
             Some(_) | None => {
                 error!("config filename must end in .toml or .ron");
@@ -89,15 +392,257 @@ impl ContainConfig {
             }
         };
 
+        // Only TOML has the position of each entry, for RON only the file is known
+        let spans: Option<TomlSpans> = match config_file.extension() {
+            Some(ext) if ext == "toml" => toml::from_str(&config_str).ok(),
+            _ => None,
+        };
+        let source = |line| Source {
+            file: filename.to_string(),
+            line,
+        };
+        let entry_source = |key, entry: &str, line| EntrySource {
+            key,
+            entry: entry.to_string(),
+            source: source(line),
+        };
+        match spans {
+            Some(spans) => {
+                for (key, list) in spans.lists() {
+                    for entry in list {
+                        let line = line_of(&config_str, entry.span().start);
+                        config
+                            .sources
+                            .push(entry_source(key, entry.get_ref(), Some(line)));
+                    }
+                }
+                if let Some(root) = spans.root.as_ref() {
+                    let line = line_of(&config_str, root.span().start);
+                    config
+                        .sources
+                        .push(entry_source("root", root.get_ref(), Some(line)));
+                }
+                for (rule, span) in config.rules.iter_mut().zip(spans.rules.iter()) {
+                    let line = line_of(&config_str, span.path.span().start);
+                    rule.source = Some(source(Some(line)));
+                }
+            }
+            None => {
+                let mut sources = Vec::new();
+                for (key, list) in config.lists() {
+                    for entry in list {
+                        sources.push(entry_source(key, entry, None));
+                    }
+                }
+                if let Some(root) = config.root.as_ref() {
+                    sources.push(entry_source("root", root, None));
+                }
+                config.sources = sources;
+                for rule in config.rules.iter_mut() {
+                    rule.source = Some(source(None));
+                }
+            }
+        }
+
         debug!("config: {:?}", config);
 
         Ok(config)
     }
 
+    /// Merge the config files in a drop-in directory, e.g. /etc/contain.d,
+    /// in filename order. Only files ending in .toml or .ron are used.
+    /// A missing directory is not an error.
+    pub fn with_drop_ins(mut self, dir: &str) -> Result<Self, Error> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                debug!("no drop-in directory {}", dir);
+                return Ok(self);
+            }
+            Err(e) => {
+                error!("Contain: could not read drop-in directory {}, {}", dir, e);
+                return Err(e);
+            }
+        };
+        let mut drop_ins = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            match path.extension() {
+                Some(ext) if (ext == "toml" || ext == "ron") && path.is_file() => {
+                    drop_ins.push(path)
+                }
+                _ => debug!("ignoring {:?} in drop-in directory", path),
+            }
+        }
+        drop_ins.sort();
+        for drop_in in drop_ins {
+            self.merge(Self::from_file(&drop_in.to_string_lossy())?);
+        }
+        Ok(self)
+    }
+
+    /// Merge another config into this one.
+    /// Lists are appended, and values that are set in `other` replace ours.
+    pub fn merge(&mut self, other: ContainConfig) {
+        if other.root.is_some() {
+            self.root = other.root;
+        }
+        self.pass_schemes.extend(other.pass_schemes);
+        self.sandbox_schemes.extend(other.sandbox_schemes);
+        self.files.extend(other.files);
+        self.dirs.extend(other.dirs);
+        self.rofiles.extend(other.rofiles);
+        self.rodirs.extend(other.rodirs);
+        self.rules.extend(other.rules);
+        self.deny_files.extend(other.deny_files);
+        self.deny_dirs.extend(other.deny_dirs);
+        self.include.extend(other.include);
+        self.env.merge(other.env);
+        if other.on_exit.is_some() {
+            self.on_exit = other.on_exit;
+        }
+        if other.timeout.is_some() {
+            self.timeout = other.timeout;
+        }
+        if other.strict.is_some() {
+            self.strict = other.strict;
+        }
+        if other.contain_root.is_some() {
+            self.contain_root = other.contain_root;
+        }
+        if other.profile.is_some() {
+            self.profile = other.profile;
+        }
+        for (user, profiles) in other.users {
+            self.users.entry(user).or_default().extend(profiles);
+        }
+        for (group, profiles) in other.groups {
+            self.groups.entry(group).or_default().extend(profiles);
+        }
+        self.sources.extend(other.sources);
+    }
+
+    /// Check if a user should be contained when logging in.
+    /// Root is only contained if `contain_root` is set.
+    pub fn contains_uid(&self, uid: usize) -> bool {
+        uid != 0 || self.contain_root == Some(true)
+    }
+
+    /// Check if the path is in one of the sandboxed schemes
+    pub fn in_sandbox(&self, path: &str) -> bool {
+        self.sandbox_schemes
+            .iter()
+            .any(|scheme| path_in_scheme(path, scheme))
+    }
+
+    /// The file and line that an entry, e.g. a path or scheme, was first read from.
+    /// The rules from `effective_rules` have the source of each entry.
+    pub fn source_of(&self, entry: &str) -> Option<&Source> {
+        self.sources
+            .iter()
+            .find(|source| source.entry == entry)
+            .map(|source| &source.source)
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .find(|rule| rule.path == entry)
+                    .and_then(|rule| rule.source.as_ref())
+            })
+    }
+
+    // The lists of entries, with their keys
+    fn lists(&self) -> [(&'static str, &Vec<String>); 8] {
+        [
+            ("pass_schemes", &self.pass_schemes),
+            ("sandbox_schemes", &self.sandbox_schemes),
+            ("files", &self.files),
+            ("dirs", &self.dirs),
+            ("rofiles", &self.rofiles),
+            ("rodirs", &self.rodirs),
+            ("deny_files", &self.deny_files),
+            ("deny_dirs", &self.deny_dirs),
+        ]
+    }
+
+    // The source of each entry in a list.
+    // An entry that is in the list more than once gets its sources in the order they were read.
+    fn list_sources(&self, key: &str, list: &[String]) -> Vec<Option<Source>> {
+        let mut used = vec![false; self.sources.len()];
+        list.iter()
+            .map(|entry| {
+                let i = (0..self.sources.len()).find(|i| {
+                    !used[*i] && self.sources[*i].key == key && self.sources[*i].entry == *entry
+                })?;
+                used[i] = true;
+                Some(self.sources[i].source.clone())
+            })
+            .collect()
+    }
+
+    // Rules for the entries in a list, each with its source
+    fn list_rules(&self, key: &str, list: &[String], rule: fn(&str) -> Rule) -> Vec<Rule> {
+        list.iter()
+            .zip(self.list_sources(key, list))
+            .map(|(path, source)| {
+                let mut rule = rule(path);
+                rule.source = source;
+                rule
+            })
+            .collect()
+    }
+
+    /// Expand the variables in all the paths, see `ConfigVars`.
+    /// Unknown variables are an error.
+    pub fn expand_vars(&mut self, vars: &ConfigVars) -> ContainResult<()> {
+        let expand = |s: &str| {
+            vars.expand(s).map_err(|e| {
+                error!("Contain: {:?}", e);
+                e
+            })
+        };
+        if let Some(root) = self.root.as_ref() {
+            self.root = Some(expand(root)?);
+        }
+        for list in [
+            &mut self.files,
+            &mut self.dirs,
+            &mut self.rofiles,
+            &mut self.rodirs,
+            &mut self.deny_files,
+            &mut self.deny_dirs,
+        ] {
+            for path in list.iter_mut() {
+                *path = expand(path)?;
+            }
+        }
+        for rule in self.rules.iter_mut() {
+            rule.path = expand(&rule.path)?;
+        }
+        for source in self.sources.iter_mut() {
+            if let Ok(expanded) = vars.expand(&source.entry) {
+                source.entry = expanded;
+            }
+        }
+        Ok(())
+    }
+
+    /// Build a config in code, with each entry checked, see `ContainConfigBuilder`
+    pub fn builder() -> ContainConfigBuilder {
+        ContainConfigBuilder::default()
+    }
+
     pub fn add_chroot(&mut self, root: &str) {
         self.root = Some(root.to_string());
     }
 
+    pub fn add_file(&mut self, file: &str) {
+        self.files.push(file.to_string());
+    }
+
+    pub fn add_rofile(&mut self, rofile: &str) {
+        self.rofiles.push(rofile.to_string());
+    }
+
     pub fn add_dir(&mut self, dir: &str) {
         self.dirs.push(dir.to_string());
     }
@@ -105,4 +650,269 @@ impl ContainConfig {
     pub fn add_rodir(&mut self, rodir: &str) {
         self.rodirs.push(rodir.to_string());
     }
+
+    pub fn add_rule(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    pub fn add_deny_file(&mut self, file: &str) {
+        self.deny_files.push(file.to_string());
+    }
+
+    pub fn add_deny_dir(&mut self, dir: &str) {
+        self.deny_dirs.push(dir.to_string());
+    }
+
+    /// All the rules in the config, with `files`, `dirs`, `rofiles` and `rodirs`
+    /// mapped onto the operations they permit.
+    pub fn effective_rules(&self) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        rules.extend(self.list_rules("files", &self.files, |f| Rule::file(f, Operation::ALL)));
+        rules.extend(self.list_rules("dirs", &self.dirs, |d| Rule::dir(d, Operation::ALL)));
+        rules.extend(self.list_rules("rofiles", &self.rofiles, |f| {
+            Rule::file(f, Operation::READ_ONLY)
+        }));
+        rules.extend(self.list_rules("rodirs", &self.rodirs, |d| {
+            Rule::dir(d, Operation::READ_ONLY)
+        }));
+        rules.extend(self.rules.iter().cloned());
+        rules
+    }
+
+    /// Compile all the rules, including any glob patterns.
+    /// The rules are compiled from the config as it is now,
+    /// so compile again after changing it.
+    pub fn rule_set(&self) -> Result<RuleSet, PatternError> {
+        RuleSet::new(self.effective_rules(), self.deny_rules())
+    }
+
+    /// Rules for `deny_files` and `deny_dirs`, which permit no operations.
+    /// A path that matches any of them is denied, regardless of other rules.
+    pub fn deny_rules(&self) -> Vec<Rule> {
+        let mut rules = self.list_rules("deny_files", &self.deny_files, |f| Rule::file(f, &[]));
+        rules.extend(self.list_rules("deny_dirs", &self.deny_dirs, |d| Rule::dir(d, &[])));
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> ConfigVars {
+        ConfigVars {
+            home: "file:/home/alice".to_string(),
+            user: "alice".to_string(),
+            uid: 1000,
+            scheme: "file".to_string(),
+        }
+    }
+
+    #[test]
+    fn expands_known_variables() {
+        let vars = vars();
+        assert_eq!(vars.expand("file:/tmp").unwrap(), "file:/tmp");
+        assert_eq!(
+            vars.expand("file:/home/${USER}/.cache").unwrap(),
+            "file:/home/alice/.cache"
+        );
+        assert_eq!(vars.expand("file:/tmp/${UID}").unwrap(), "file:/tmp/1000");
+        assert_eq!(
+            vars.expand("${HOME}/.config").unwrap(),
+            "file:/home/alice/.config"
+        );
+        assert_eq!(
+            vars.expand("${SCHEME}:/run/${UID}-${USER}").unwrap(),
+            "file:/run/1000-alice"
+        );
+        assert_eq!(vars.expand("file:/cost/$5").unwrap(), "file:/cost/$5");
+    }
+
+    #[test]
+    fn rejects_unknown_variables() {
+        let vars = vars();
+        assert!(vars.expand("file:/home/${USERNAME}").is_err());
+        assert!(vars.expand("file:/home/${USER").is_err());
+        assert!(vars.expand("file:/home/${}").is_err());
+
+        let no_user = ConfigVars::default();
+        assert_eq!(no_user.expand("file:/tmp").unwrap(), "file:/tmp");
+        assert!(no_user.expand("file:/tmp/${UID}").is_err());
+    }
+
+    // A new, empty directory for test files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("contain_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn merges_includes() {
+        let dir = test_dir("includes");
+        fs::write(
+            dir.join("base.toml"),
+            "pass_schemes = [\"rand\"]\nsandbox_schemes = [\"file\"]\nfiles = []\n\
+             dirs = [\"file:/tmp\"]\nrofiles = []\nrodirs = []\nroot = \"file:/base\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.toml"),
+            "include = [\"base.toml\"]\npass_schemes = [\"null\"]\nsandbox_schemes = []\n\
+             files = []\ndirs = []\nrofiles = []\n\nrodirs = [\"file:/bin\"]\nroot = \"file:/main\"\n",
+        )
+        .unwrap();
+        let main = dir.join("main.toml").to_string_lossy().to_string();
+        let config = ContainConfig::from_file(&main).unwrap();
+        assert_eq!(config.pass_schemes, ["rand", "null"]);
+        assert_eq!(config.dirs, ["file:/tmp"]);
+        assert_eq!(config.rodirs, ["file:/bin"]);
+        assert_eq!(config.root.as_deref(), Some("file:/main"));
+        assert!(config.include.is_empty());
+        let source = config.source_of("file:/bin").unwrap();
+        assert_eq!(source.file, main);
+        assert_eq!(source.line, Some(8));
+        let source = config.source_of("file:/tmp").unwrap();
+        assert!(source.file.ends_with("base.toml"));
+        assert_eq!(source.line, Some(4));
+        let rules = config.effective_rules();
+        let rule = rules.iter().find(|r| r.path == "file:/bin").unwrap();
+        assert_eq!(rule.source.as_ref().unwrap().line, Some(8));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn remembers_the_source_of_each_rule() {
+        let dir = test_dir("sources");
+        fs::write(
+            dir.join("10-a.toml"),
+            "# dirs = [\"file:/tmp\"]\nrofiles = [\"file:/tmp\"]\n\
+             dirs = [\n  \"file:/bin\",\n  \"file:/tmp\",\n]\n\
+             [[rules]]\npath = \"file:/bin\"\nallow = []\n",
+        )
+        .unwrap();
+        fs::write(dir.join("20-b.toml"), "\ndirs = [\"file:/tmp\"]\n").unwrap();
+        let config = ContainConfig::default()
+            .with_drop_ins(&dir.to_string_lossy())
+            .unwrap();
+        let rules = config.effective_rules();
+        let sources: Vec<(&str, String)> = rules
+            .iter()
+            .map(|rule| {
+                let source = rule.source.as_ref().unwrap();
+                let file = Path::new(&source.file).file_name().unwrap();
+                (
+                    rule.path.as_str(),
+                    format!("{}:{}", file.to_string_lossy(), source.line.unwrap()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("file:/bin", "10-a.toml:4".to_string()),
+                ("file:/tmp", "10-a.toml:5".to_string()),
+                ("file:/tmp", "20-b.toml:2".to_string()),
+                ("file:/tmp", "10-a.toml:2".to_string()),
+                ("file:/bin", "10-a.toml:8".to_string()),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn detects_include_cycle() {
+        let dir = test_dir("cycle");
+        fs::write(dir.join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        fs::write(dir.join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+        let a = dir.join("a.toml").to_string_lossy().to_string();
+        let e = ContainConfig::from_file(&a).unwrap_err();
+        assert!(e.to_string().contains("include cycle"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn merges_drop_ins_in_order() {
+        let dir = test_dir("drop_ins");
+        // a fragment only needs the keys it adds to
+        let list = |name: &str| format!("dirs = [\"file:/{}\"]\n", name);
+        fs::write(dir.join("20-b.toml"), list("b")).unwrap();
+        fs::write(dir.join("10-a.toml"), list("a")).unwrap();
+        fs::write(dir.join("30-c.txt"), list("c")).unwrap();
+        let mut config = ContainConfig::default();
+        config.add_dir("file:/main");
+        let config = config
+            .with_drop_ins(&dir.to_string_lossy())
+            .unwrap()
+            .with_drop_ins(&dir.join("missing").to_string_lossy())
+            .unwrap();
+        assert_eq!(config.dirs, ["file:/main", "file:/a", "file:/b"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn expands_all_paths() {
+        let mut config = ContainConfig::default();
+        config.add_chroot("file:/jail/${USER}");
+        config.add_dir("${HOME}");
+        config.add_rodir("file:/home/${USER}/.ssh");
+        config.add_deny_file("${HOME}/.secret");
+        config.add_rule(Rule::dir("file:/tmp/${UID}", Operation::ALL));
+        config.expand_vars(&vars()).unwrap();
+        assert_eq!(config.root.as_deref(), Some("file:/jail/alice"));
+        assert_eq!(config.dirs, ["file:/home/alice"]);
+        assert_eq!(config.rodirs, ["file:/home/alice/.ssh"]);
+        assert_eq!(config.deny_files, ["file:/home/alice/.secret"]);
+        assert_eq!(config.rules[0].path, "file:/tmp/1000");
+    }
+
+    #[test]
+    fn config_keys_match_fields() {
+        let mut config = ContainConfig::use_defaults();
+        config.root = Some("file:/root".to_string());
+        config.strict = Some(true);
+        config.contain_root = Some(true);
+        config.env.clear = Some(false);
+        config.on_exit = Some(OnExit::Wait);
+        config.timeout = Some(600);
+        config.profile = Some(ProfileInfo::default());
+        config.users.insert("alice".to_string(), vec![]);
+        config.groups.insert("wheel".to_string(), vec![]);
+        let table: toml::Table = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        let mut keys: Vec<&str> = table.keys().map(|key| key.as_str()).collect();
+        let mut known = CONFIG_KEYS.to_vec();
+        keys.sort();
+        known.sort();
+        assert_eq!(keys, known);
+    }
+
+    #[test]
+    fn writes_and_reads_back() {
+        let mut config = ContainConfig::use_defaults();
+        config.add_rule(Rule::dir(
+            "file:/var/log",
+            &[Operation::Read, Operation::Append],
+        ));
+        config.profile = Some(ProfileInfo {
+            name: "test".to_string(),
+            description: "round trip".to_string(),
+        });
+        config
+            .users
+            .insert("alice".to_string(), vec!["shell".to_string()]);
+        let dir = test_dir("to_file");
+        for ext in ["toml", "ron"] {
+            let filename = dir.join(format!("contain.{}", ext));
+            let filename = filename.to_string_lossy();
+            config.to_file(&filename).unwrap();
+            let read = ContainConfig::from_file(&filename).unwrap();
+            assert_eq!(read.to_toml().unwrap(), config.to_toml().unwrap());
+            assert_eq!(read.rules[0].allow, [Operation::Read, Operation::Append]);
+        }
+        assert!(config
+            .to_file(&dir.join("contain.txt").to_string_lossy())
+            .is_err());
+    }
 }
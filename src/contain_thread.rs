@@ -1,4 +1,4 @@
-use std::sync::{Arc, LockResult, RwLock, RwLockReadGuard};
+use std::sync::{Arc, LockResult, RwLock, RwLockReadGuard, Weak};
 use std::thread::{self, JoinHandle};
 
 use event::{EventFlags, RawEventQueue};
@@ -15,14 +15,20 @@ pub struct ContainThread {
     config: Arc<RwLock<ContainConfig>>,
     namespace: usize,
     shutdown_pipe: usize,
-    thread_handle: JoinHandle<()>,
+    // taken when the thread is joined on drop
+    thread_handle: Option<JoinHandle<()>>,
 }
 
 impl ContainThread {
     // Create the namespace and spawn the scheme manager(s).
     // We use an RwLock for the config so we can start with
     // the basic space and add the user directory after.
+    // The rules are compiled here, once, and shared by the schemes.
     pub fn new(config: ContainConfig) -> ContainResult<Self> {
+        let rules = Arc::new(config.rule_set().map_err(|e| {
+            error!("{}", e);
+            ContainError::ConfigError
+        })?);
         let config_arc = Arc::new(RwLock::new(config));
         let config_lock = config_arc.read().map_err(|e| {
             error!("could not get config lock: {}", e);
@@ -56,7 +62,7 @@ impl ContainThread {
                 error!("could not create scheme {}:, {}", scheme_name, e);
                 ContainError::syscall_error(e)
             })?;
-            let scheme_handler = FilterScheme::new(&scheme_name, config_arc.clone());
+            let scheme_handler = FilterScheme::new(&scheme_name, config_arc.clone(), rules.clone());
             schemes.push((scheme_fd, scheme_handler));
         }
         setrens(
@@ -212,7 +218,7 @@ impl ContainThread {
             config: config_arc,
             namespace: new_ns,
             shutdown_pipe: write_pipe,
-            thread_handle: scheme_thread,
+            thread_handle: Some(scheme_thread),
         })
     }
 
@@ -221,20 +227,34 @@ impl ContainThread {
     }
 
     pub fn thread(&self) -> &JoinHandle<()> {
-        &self.thread_handle
+        self.thread_handle
+            .as_ref()
+            .expect("the thread is only joined on drop")
     }
 
     pub fn config(&self) -> LockResult<RwLockReadGuard<ContainConfig>> {
         self.config.read()
     }
+
+    /// The config shared with the schemes, which is freed
+    /// once this is dropped and the scheme thread has finished
+    pub fn shared_config(&self) -> Weak<RwLock<ContainConfig>> {
+        Arc::downgrade(&self.config)
+    }
 }
 
 impl Drop for ContainThread {
-    // Shutdown the thread by sending a message on the shutdown pipe
+    // Shutdown the thread by sending a message on the shutdown pipe,
+    // and wait for it, so the schemes are closed when drop returns
     // TODO: Implement drop of namespace
     fn drop(&mut self) {
         debug!("shutdown scheme thread");
 
         let _ = libredox::call::write(self.shutdown_pipe, "shutdown scheme".as_bytes());
+        if let Some(thread_handle) = self.thread_handle.take() {
+            if thread_handle.join().is_err() {
+                error!("scheme thread panicked");
+            }
+        }
     }
 }
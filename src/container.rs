@@ -0,0 +1,180 @@
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus};
+use std::sync::mpsc::Sender;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{debug, error};
+
+use crate::runner::{
+    end_group, kill_group, set_env, spawn_in_namespace, start_timer, validate_and_warn, OnExit,
+};
+use crate::{ContainConfig, ContainError, ContainExit, ContainResult, ContainThread};
+
+/// Starts contained commands without waiting for them, see `Container::spawn`
+pub struct Container;
+
+impl Container {
+    /// Validate the config, create a namespace for it,
+    /// and spawn the command in that namespace without waiting for it.
+    /// The entries that validation drops are logged as warnings.
+    /// The command runs in a new process group, like with `run_contained`,
+    /// but signals sent to this process are not forwarded to it.
+    /// `CONTAIN_NAME` is set to the namespace.
+    /// Streams set to `Stdio::piped()` on the command are in the returned child.
+    /// The working directory is changed to inside the namespace, but the command
+    /// must not set a uid or gid, since they would be applied before entering it.
+    pub fn spawn(config: ContainConfig, mut command: Command) -> ContainResult<ContainedChild> {
+        let config = validate_and_warn(config)?;
+        let on_exit = config.on_exit.unwrap_or_default();
+        let timeout = config.timeout.map(Duration::from_secs);
+        let contain_thread = ContainThread::new(config).map_err(|e| {
+            error!("could not get contain thread: {}", e);
+            e
+        })?;
+        let namespace = contain_thread.namespace();
+        set_env(
+            &mut command,
+            &contain_thread
+                .config()
+                .map_err(|_| ContainError::PoisonError)?
+                .env,
+            &namespace.to_string(),
+        );
+
+        let mut child = spawn_in_namespace(&mut command, namespace, None)?;
+        debug!(
+            "contain: spawned pid {} in container {}",
+            child.id(),
+            namespace
+        );
+        let timer = timeout.map(|timeout| start_timer(child.id() as usize, timeout));
+        Ok(ContainedChild {
+            stdin: child.stdin.take(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            child,
+            on_exit,
+            timer,
+            ended: false,
+            timed_out: false,
+            contain_thread,
+        })
+    }
+}
+
+/// A command running in its own container, returned by `Container::spawn`.
+/// The container's schemes are served until the handle is dropped.
+/// If `wait` has not returned by then, the command and the processes it left
+/// are killed and waited for, whatever `on_exit` is, before the namespace thread
+/// is shut down.
+pub struct ContainedChild {
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    child: Child,
+    on_exit: OnExit,
+    timer: Option<(Sender<()>, JoinHandle<bool>)>,
+    // true once the processes left in the group have been dealt with
+    ended: bool,
+    timed_out: bool,
+    // dropped last, so the schemes are served until the processes are gone
+    contain_thread: ContainThread,
+}
+
+impl ContainedChild {
+    /// The pid of the command, which is also its process group
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn namespace(&self) -> usize {
+        self.contain_thread.namespace()
+    }
+
+    /// The thread serving the container's schemes
+    pub fn contain_thread(&self) -> &ContainThread {
+        &self.contain_thread
+    }
+
+    /// Wait for the command to exit, then handle the processes that are left
+    /// according to `on_exit`. Stdin is closed first, so the command doesn't wait for it.
+    /// Returns `TimedOut` if the config's timeout ran out.
+    pub fn wait(&mut self) -> ContainResult<ContainExit> {
+        drop(self.stdin.take());
+        let status = self.child.wait().map_err(|e| {
+            error!("wait for {} returned error: {}", self.id(), e);
+            ContainError::io_error(e)
+        })?;
+        self.stop_timer();
+        if !self.ended {
+            let on_exit = if self.timed_out {
+                OnExit::Kill
+            } else {
+                self.on_exit
+            };
+            end_group(self.id() as usize, Some(self.namespace()), on_exit);
+            self.ended = true;
+        }
+        self.exit(status)
+    }
+
+    /// Like `wait`, but return `None` right away if the command is still running.
+    /// This only waits for the command, the processes it left are handled
+    /// by `wait`, which then returns right away, or killed when the child is dropped.
+    pub fn try_wait(&mut self) -> ContainResult<Option<ContainExit>> {
+        match self.child.try_wait() {
+            Ok(Some(status)) => {
+                self.stop_timer();
+                self.exit(status).map(Some)
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                error!("wait for {} returned error: {}", self.id(), e);
+                Err(ContainError::io_error(e))
+            }
+        }
+    }
+
+    /// Send a signal to every process in the container
+    pub fn kill(&mut self, signal: i32) -> ContainResult<()> {
+        if self.ended {
+            return Ok(());
+        }
+        kill_group(self.id() as usize, signal).map_err(|e| {
+            error!("could not signal container {}: {}", self.namespace(), e);
+            ContainError::io_error(e)
+        })
+    }
+
+    // The command has exited, stop the timer once and remember if it ran out.
+    // This doesn't block, the timer returns as soon as it is stopped.
+    fn stop_timer(&mut self) {
+        if let Some((done, timer)) = self.timer.take() {
+            drop(done);
+            self.timed_out = timer.join().unwrap_or(false);
+        }
+    }
+
+    fn exit(&self, status: ExitStatus) -> ContainResult<ContainExit> {
+        if self.timed_out {
+            return Err(ContainError::TimedOut);
+        }
+        Ok(status.into())
+    }
+}
+
+impl Drop for ContainedChild {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        if let Ok(None) = self.child.try_wait() {
+            debug!("contain: killing container {}", self.namespace());
+            let _ = kill_group(self.id() as usize, libc::SIGKILL);
+        }
+        let _ = self.child.wait();
+        self.stop_timer();
+        // nothing may outlive the namespace thread, or keep the drop waiting
+        end_group(self.id() as usize, Some(self.namespace()), OnExit::Kill);
+    }
+}
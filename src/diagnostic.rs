@@ -0,0 +1,143 @@
+use std::fmt;
+
+use crate::{ContainConfig, Source};
+
+/// What is wrong with a config entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A pass or sandbox scheme that does not exist
+    UnknownScheme,
+    /// A path that is not in one of the sandboxed schemes
+    NotSandboxed,
+    /// A scheme that is in both pass_schemes and sandbox_schemes
+    PassedAndSandboxed,
+    /// A scheme name with ':' or '/' in it
+    InvalidScheme,
+    /// A path that is not in the form "scheme:/path"
+    NotAPath,
+    /// An entry that a broader dir already allows, see `related`
+    Redundant,
+    /// A read-only entry that is writable because of another entry, see `related`
+    Shadowed,
+    /// A key in the config file that is not part of the config
+    UnknownKey,
+    /// A path with a variable that can't be expanded
+    UnknownVariable,
+    /// A path with a glob pattern that can't be compiled
+    InvalidPattern,
+}
+
+impl DiagnosticKind {
+    /// A short name for the kind, e.g. for JSON output
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::UnknownScheme => "unknown-scheme",
+            DiagnosticKind::NotSandboxed => "not-sandboxed",
+            DiagnosticKind::PassedAndSandboxed => "passed-and-sandboxed",
+            DiagnosticKind::InvalidScheme => "invalid-scheme",
+            DiagnosticKind::NotAPath => "not-a-path",
+            DiagnosticKind::Redundant => "redundant",
+            DiagnosticKind::Shadowed => "shadowed",
+            DiagnosticKind::UnknownKey => "unknown-key",
+            DiagnosticKind::UnknownVariable => "unknown-variable",
+            DiagnosticKind::InvalidPattern => "invalid-pattern",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiagnosticKind::UnknownScheme => write!(f, "scheme is not available"),
+            DiagnosticKind::NotSandboxed => write!(f, "path is not in a sandboxed scheme"),
+            DiagnosticKind::PassedAndSandboxed => {
+                write!(f, "scheme is in both pass_schemes and sandbox_schemes")
+            }
+            DiagnosticKind::InvalidScheme => write!(f, "scheme must not contain ':' or '/'"),
+            DiagnosticKind::NotAPath => write!(f, "path must be in the form scheme:/path"),
+            DiagnosticKind::Redundant => write!(f, "already allowed by"),
+            DiagnosticKind::Shadowed => write!(f, "read-only entry is writable because of"),
+            DiagnosticKind::UnknownKey => write!(f, "unknown key"),
+            DiagnosticKind::UnknownVariable => write!(f, "unknown or unterminated variable"),
+            DiagnosticKind::InvalidPattern => write!(f, "invalid glob pattern"),
+        }
+    }
+}
+
+/// A problem found while validating or linting the config.
+/// In strict mode it is an error, otherwise the entry is dropped with a warning.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigDiagnostic {
+    pub kind: DiagnosticKind,
+    /// The scheme or path that has the problem
+    pub entry: String,
+    /// Where the entry was read from, if known
+    pub source: Option<Source>,
+    /// The other entry involved, e.g. the dir that makes an entry redundant
+    pub related: Option<String>,
+}
+
+impl ConfigDiagnostic {
+    pub fn new(kind: DiagnosticKind, entry: &str, config: &ContainConfig) -> Self {
+        Self {
+            kind,
+            entry: entry.to_string(),
+            source: config.source_of(entry).cloned(),
+            related: None,
+        }
+    }
+
+    /// The diagnostic as a JSON object
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"kind\": {}, \"entry\": {}",
+            json_string(self.kind.name()),
+            json_string(&self.entry)
+        );
+        if let Some(source) = self.source.as_ref() {
+            json.push_str(&format!(", \"file\": {}", json_string(&source.file)));
+            if let Some(line) = source.line {
+                json.push_str(&format!(", \"line\": {}", line));
+            }
+        }
+        if let Some(related) = self.related.as_ref() {
+            json.push_str(&format!(", \"related\": {}", json_string(related)));
+        }
+        json.push_str(&format!(
+            ", \"message\": {}}}",
+            json_string(&self.to_string())
+        ));
+        json
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(source) = self.source.as_ref() {
+            write!(f, "{}: ", source)?;
+        }
+        write!(f, "{}: {}", self.entry, self.kind)?;
+        if let Some(related) = self.related.as_ref() {
+            write!(f, " {}", related)?;
+        }
+        Ok(())
+    }
+}
+
+// Quote and escape a string for JSON
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+
+use crate::glob::{Pattern, PatternError};
+
+/// The environment of the contained command, the `[env]` table of the config.
+/// Unless `clear` is false, only the variables in `allow` are passed on.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvConfig {
+    /// start from an empty environment, default true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clear: Option<bool>,
+    /// variables to pass on when the environment is cleared, e.g. "PATH" or "LC_*"
+    pub allow: Vec<String>,
+    /// variables to set
+    pub set: BTreeMap<String, String>,
+    /// variables to remove
+    pub unset: Vec<String>,
+}
+
+/// The `allow` entries of an `EnvConfig`, compiled, see `EnvConfig::allow_list`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllowList {
+    patterns: Vec<Pattern>,
+}
+
+impl AllowList {
+    /// Check if a variable is allowed, by its name or a glob pattern
+    pub fn allows(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(name))
+    }
+}
+
+/// The keys of the `[env]` table
+pub(crate) const ENV_KEYS: &[&str] = &["clear", "allow", "set", "unset"];
+
+impl EnvConfig {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Merge `other` into self. `clear` is overridden if `other` has it,
+    /// a variable that is set is no longer unset, and the other way around.
+    pub fn merge(&mut self, other: EnvConfig) {
+        if other.clear.is_some() {
+            self.clear = other.clear;
+        }
+        self.allow.extend(other.allow);
+        for (name, value) in other.set {
+            self.unset.retain(|unset| unset != &name);
+            self.set.insert(name, value);
+        }
+        for name in other.unset {
+            self.set.remove(&name);
+            self.unset.push(name);
+        }
+    }
+
+    /// Compile `allow`, which may have glob patterns, failing on any invalid one.
+    /// It is compiled from the config as it is now, so compile again after changing it.
+    pub fn allow_list(&self) -> Result<AllowList, PatternError> {
+        let patterns = self
+            .allow
+            .iter()
+            .map(|allow| Pattern::new(allow))
+            .collect::<Result<_, _>>()?;
+        Ok(AllowList { patterns })
+    }
+
+    /// Apply the policy to the environment of this process, for the command.
+    /// Variables that were already set on the command are kept.
+    /// The config is expected to be validated, so with an invalid pattern
+    /// in `allow`, no variable is passed on.
+    pub fn apply(&self, command: &mut Command) {
+        if self.clear.unwrap_or(true) {
+            let allow = self.allow_list().unwrap_or_else(|e| {
+                error!("environment: {}", e);
+                AllowList::default()
+            });
+            let explicit: Vec<_> = command
+                .get_envs()
+                .filter_map(|(name, value)| Some((name.to_owned(), value?.to_owned())))
+                .collect();
+            command.env_clear();
+            for (name, value) in std::env::vars_os() {
+                if name.to_str().is_some_and(|name| allow.allows(name)) {
+                    command.env(name, value);
+                }
+            }
+            command.envs(explicit);
+        }
+        for name in self.unset.iter() {
+            command.env_remove(name);
+        }
+        command.envs(self.set.iter());
+        debug!("environment: {:?}", command.get_envs().collect::<Vec<_>>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_set_and_unset() {
+        let mut env = EnvConfig {
+            allow: vec!["PATH".to_string()],
+            unset: vec!["EDITOR".to_string()],
+            ..Default::default()
+        };
+        let mut other = EnvConfig {
+            clear: Some(false),
+            unset: vec!["PAGER".to_string()],
+            ..Default::default()
+        };
+        other.set.insert("EDITOR".to_string(), "vi".to_string());
+        env.merge(other);
+        assert_eq!(env.clear, Some(false));
+        assert_eq!(env.unset, ["PAGER"]);
+        assert_eq!(env.set.get("EDITOR").map(|s| s.as_str()), Some("vi"));
+    }
+
+    #[test]
+    fn allows_patterns() {
+        let env = EnvConfig {
+            allow: vec!["TERM".to_string(), "LC_*".to_string()],
+            ..Default::default()
+        };
+        let allow = env.allow_list().unwrap();
+        assert!(allow.allows("TERM"));
+        assert!(allow.allows("LC_ALL"));
+        assert!(!allow.allows("TERMINAL"));
+        assert!(!allow.allows("SECRET_TOKEN"));
+
+        let env = EnvConfig {
+            allow: vec!["LC_[".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(env.allow_list().unwrap_err().pattern, "LC_[");
+    }
+
+    #[test]
+    fn keeps_explicit_variables() {
+        let mut env = EnvConfig::default();
+        env.set.insert("CONTAIN".to_string(), "1".to_string());
+        let mut command = Command::new("true");
+        command.env("HOME", "file:/home/user");
+        env.apply(&mut command);
+        let mut envs: Vec<_> = command
+            .get_envs()
+            .filter_map(|(name, value)| Some((name.to_str()?, value?.to_str()?)))
+            .collect();
+        envs.sort();
+        assert_eq!(envs, [("CONTAIN", "1"), ("HOME", "file:/home/user")]);
+    }
+}
@@ -0,0 +1,324 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+
+/// A compiled glob pattern for matching paths.
+/// `*` matches within one path component, `**` matches across components,
+/// `?` matches one character, and `[a-z]`, `[!abc]` match a character class.
+/// A `\` outside a class makes the next character literal, e.g. "file:/data/\[build]".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyInComponent,
+    AnyPath,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// A glob pattern that could not be compiled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternError {
+    pub pattern: String,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern {}: {}", self.pattern, self.reason)
+    }
+}
+
+/// Check if the string has any glob special characters that are not escaped.
+pub fn is_pattern(s: &str) -> bool {
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// The string with each escaped character in place of its escape.
+pub fn unescape(s: &str) -> Cow<'_, str> {
+    if !s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next().unwrap_or('\\')),
+            c => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, PatternError> {
+        let error = |reason| PatternError {
+            pattern: pattern.to_string(),
+            reason,
+        };
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => tokens.push(Token::Char(chars.next().unwrap_or('\\'))),
+                '?' => tokens.push(Token::AnyChar),
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'*') {
+                        return Err(error("more than two '*' in a row"));
+                    }
+                    tokens.push(Token::AnyPath);
+                }
+                '*' => tokens.push(Token::AnyInComponent),
+                '[' => {
+                    let negated = matches!(chars.peek(), Some('!') | Some('^'));
+                    if negated {
+                        chars.next();
+                    }
+                    let mut ranges = Vec::new();
+                    let mut closed = false;
+                    // a ']' at the start of the class is a literal
+                    let mut first = true;
+                    while let Some(start) = chars.next() {
+                        if start == ']' && !first {
+                            closed = true;
+                            break;
+                        }
+                        first = false;
+                        let mut lookahead = chars.clone();
+                        if lookahead.next() == Some('-')
+                            && !matches!(lookahead.peek(), Some(']') | None)
+                        {
+                            chars.next();
+                            let end = chars.next().unwrap();
+                            if end < start {
+                                return Err(error("character range is out of order"));
+                            }
+                            ranges.push((start, end));
+                        } else {
+                            ranges.push((start, start));
+                        }
+                    }
+                    if !closed {
+                        return Err(error("unclosed '['"));
+                    }
+                    tokens.push(Token::Class { negated, ranges });
+                }
+                c => tokens.push(Token::Char(c)),
+            }
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Check if the pattern matches the whole path.
+    pub fn matches(&self, path: &str) -> bool {
+        MEMO.with(|memo| {
+            let mut memo = memo.borrow_mut();
+            memo.clear();
+            memo.resize((self.tokens.len() + 1) * (path.len() + 1), None);
+            Matcher {
+                tokens: &self.tokens,
+                path,
+                memo: &mut memo,
+            }
+            .matches(0, 0)
+        })
+    }
+
+    /// Check if the pattern matches the path, or a directory containing the path.
+    pub fn matches_dir(&self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        if self.matches(path) {
+            return true;
+        }
+        path.match_indices('/')
+            .any(|(i, _)| i > 0 && self.matches(&path[..i]))
+    }
+}
+
+thread_local! {
+    // The matcher's memo, reused so that matching a path doesn't allocate
+    static MEMO: RefCell<Vec<Option<bool>>> = const { RefCell::new(Vec::new()) };
+}
+
+// Matches tokens against a path, remembering the outcome for each position
+// in the tokens and the path, so each pair is only tried once
+// no matter how many '*' or '**' the pattern has.
+// Positions in the path are byte offsets, always at a character boundary.
+struct Matcher<'a> {
+    tokens: &'a [Token],
+    path: &'a str,
+    memo: &'a mut [Option<bool>],
+}
+
+impl<'a> Matcher<'a> {
+    // Check if the tokens from t match the path from p
+    fn matches(&mut self, t: usize, p: usize) -> bool {
+        let key = t * (self.path.len() + 1) + p;
+        if let Some(matched) = self.memo[key] {
+            return matched;
+        }
+        let (tokens, path): (&'a [Token], &'a str) = (self.tokens, self.path);
+        let rest = &path[p..];
+        let next = rest.chars().next();
+        let after = |c: char| p + c.len_utf8();
+        let matched = match tokens.get(t) {
+            None => rest.is_empty(),
+            Some(Token::Char(c)) => next == Some(*c) && self.matches(t + 1, after(*c)),
+            Some(Token::AnyChar) => match next {
+                Some(c) if c != '/' => self.matches(t + 1, after(c)),
+                _ => false,
+            },
+            Some(Token::Class { negated, ranges }) => match next {
+                Some(c) if c != '/' => {
+                    let in_class = ranges.iter().any(|(start, end)| *start <= c && c <= *end);
+                    in_class != *negated && self.matches(t + 1, after(c))
+                }
+                _ => false,
+            },
+            Some(Token::AnyInComponent) => {
+                // up to the end of the component
+                let len = rest.find('/').unwrap_or(rest.len());
+                rest[..len]
+                    .char_indices()
+                    .map(|(i, _)| p + i)
+                    .chain(std::iter::once(p + len))
+                    .any(|i| self.matches(t + 1, i))
+            }
+            Some(Token::AnyPath) => {
+                // "**/" also matches no directories at all
+                (tokens.get(t + 1) == Some(&Token::Char('/')) && self.matches(t + 2, p))
+                    || rest
+                        .char_indices()
+                        .map(|(i, _)| p + i)
+                        .chain(std::iter::once(path.len()))
+                        .any(|i| self.matches(t + 1, i))
+            }
+        };
+        self.memo[key] = Some(matched);
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Pattern::new(pattern).unwrap().matches(path)
+    }
+
+    #[test]
+    fn literal() {
+        assert!(matches("file:/bin/ls", "file:/bin/ls"));
+        assert!(!matches("file:/bin/ls", "file:/bin/lsblk"));
+        assert!(!matches("file:/bin/ls", "file:/bin/l"));
+    }
+
+    #[test]
+    fn star_stays_in_component() {
+        assert!(matches("file:/bin/*", "file:/bin/ls"));
+        assert!(matches("file:/bin/*", "file:/bin/"));
+        assert!(!matches("file:/bin/*", "file:/bin/sub/ls"));
+        assert!(matches("file:/lib/*.so", "file:/lib/libc.so"));
+        assert!(!matches("file:/lib/*.so", "file:/lib/libc.so.6"));
+        assert!(matches("file:/home/*/.ssh", "file:/home/alice/.ssh"));
+        assert!(!matches("file:/home/*/.ssh", "file:/home/alice/x/.ssh"));
+    }
+
+    #[test]
+    fn double_star_crosses_components() {
+        assert!(matches("file:/usr/**/*.so", "file:/usr/lib/libc.so"));
+        assert!(matches("file:/usr/**/*.so", "file:/usr/lib/x86_64/libc.so"));
+        assert!(matches("file:/usr/**/*.so", "file:/usr/libc.so"));
+        assert!(!matches("file:/usr/**/*.so", "file:/usr/lib/libc.so.6"));
+        assert!(matches("file:/home/**", "file:/home/alice/.ssh/id_rsa"));
+        assert!(!matches("file:/home/**", "file:/homes/alice"));
+    }
+
+    #[test]
+    fn question_mark_and_classes() {
+        assert!(matches("file:/dev/tty?", "file:/dev/tty1"));
+        assert!(!matches("file:/dev/tty?", "file:/dev/tty12"));
+        assert!(!matches("file:/tmp?x", "file:/tmp/x"));
+        assert!(matches("file:/dev/tty[0-9]", "file:/dev/tty5"));
+        assert!(!matches("file:/dev/tty[0-9]", "file:/dev/ttya"));
+        assert!(matches("file:/dev/tty[!0-9]", "file:/dev/ttya"));
+        assert!(matches("file:/dev/tty[^0-9]", "file:/dev/ttya"));
+        assert!(matches("file:/x/[abc-]", "file:/x/-"));
+        assert!(matches("file:/x/[]]", "file:/x/]"));
+        assert!(!matches("file:/x[/]y", "file:/x/y"));
+    }
+
+    #[test]
+    fn dir_matches_contents() {
+        let pattern = Pattern::new("file:/home/*/.cache").unwrap();
+        assert!(pattern.matches_dir("file:/home/alice/.cache"));
+        assert!(pattern.matches_dir("file:/home/alice/.cache/"));
+        assert!(pattern.matches_dir("file:/home/alice/.cache/x/y"));
+        assert!(!pattern.matches_dir("file:/home/alice/.cachex"));
+        assert!(!pattern.matches_dir("file:/home/alice"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(Pattern::new("file:/x/[abc").is_err());
+        assert!(Pattern::new("file:/x/[z-a]").is_err());
+        assert!(Pattern::new("file:/x/***").is_err());
+    }
+
+    #[test]
+    fn escapes_are_literal() {
+        assert!(matches("file:/data/\\[build]", "file:/data/[build]"));
+        assert!(!matches("file:/data/\\[build]", "file:/data/b"));
+        assert!(matches("file:/x/\\*", "file:/x/*"));
+        assert!(!matches("file:/x/\\*", "file:/x/y"));
+        assert!(matches("file:/x/\\[*", "file:/x/[y"));
+        assert!(Pattern::new("file:/x/\\[").is_ok());
+        assert_eq!(unescape("file:/data/\\[build]"), "file:/data/[build]");
+        assert_eq!(unescape("file:/bin/ls"), "file:/bin/ls");
+    }
+
+    #[test]
+    fn matches_non_ascii() {
+        assert!(matches("file:/x/?", "file:/x/é"));
+        assert!(!matches("file:/x/?", "file:/x/éé"));
+        assert!(matches("file:/x/*.txt", "file:/x/über.txt"));
+        assert!(matches("file:/x/[à-ÿ]", "file:/x/é"));
+        assert!(matches("file:/ü/**", "file:/ü/ö/ä"));
+    }
+
+    #[test]
+    fn many_stars_match_quickly() {
+        let pattern = Pattern::new(&"file:/**/a*".repeat(12)).unwrap();
+        let path = format!("file:/{}b", "a/".repeat(40));
+        assert!(!pattern.matches(&path));
+    }
+
+    #[test]
+    fn detects_patterns() {
+        assert!(is_pattern("file:/bin/*"));
+        assert!(is_pattern("file:/dev/tty?"));
+        assert!(is_pattern("file:/dev/tty[0-9]"));
+        assert!(!is_pattern("file:/bin/ls"));
+        assert!(!is_pattern("file:/data/\\[build]"));
+        assert!(!is_pattern("file:/x/\\*"));
+    }
+}
@@ -1,11 +1,41 @@
+mod config_builder;
 mod contain_config;
 mod contain_thread;
+mod container;
+mod diagnostic;
+mod env;
 mod filterscheme;
+mod glob;
+mod lint;
+mod policy;
+mod profile;
+mod registry;
+mod rule;
+mod run_as;
 mod runner;
+mod signals;
 
-pub use contain_config::ContainConfig;
+pub use config_builder::ContainConfigBuilder;
+pub use contain_config::{ConfigVars, ContainConfig, EntrySource, Source};
 pub use contain_thread::ContainThread;
-pub use runner::{run_contained, run_in_namespace, run_not_contained};
+pub use container::{ContainedChild, Container};
+pub use diagnostic::{ConfigDiagnostic, DiagnosticKind};
+pub use env::{AllowList, EnvConfig};
+pub use glob::{Pattern, PatternError};
+pub use lint::{diagnostics_to_json, lint_config, lint_file};
+pub use policy::{check, explain, resolve_explained, Decision, Explanation, Reason};
+pub use profile::{
+    list_profiles, load_profile, profile_file, select_config, select_user_config, ProfileInfo,
+    SelectDirs, GROUPS_DIR, PROFILE_DIR, USERS_DIR,
+};
+pub use registry::{find_container, list_containers, register, unregister, ContainerInfo, RUN_DIR};
+pub use rule::{Operation, Rule, RuleKind, RuleSet};
+pub use run_as::RunAs;
+pub use runner::{
+    exec_registered, run_contained, run_in_namespace, run_not_contained, run_registered,
+    validate_config, ContainExit, OnExit, KILL_GRACE_PERIOD,
+};
+pub use signals::FORWARDED_SIGNALS;
 
 // TODO: Check ownership of files (e.g. pty:/5) before making them visible
 // TODO: Add tests
@@ -14,6 +44,7 @@ pub use runner::{run_contained, run_in_namespace, run_not_contained};
 // TODO: Discuss the future of the chroot-incompatible fpath syscall,
 // since it doesn't work for forwarded descriptors
 
+#[deprecated(note = "exec failures are returned as `ContainError::ExecFailed`")]
 pub const CONTAIN_EXEC_FAIL_EXIT: i32 = 13;
 
 pub type ContainResult<T> = core::result::Result<T, ContainError>;
@@ -26,6 +57,14 @@ pub enum ContainError {
     SyscallError(libredox::error::Error),
     PoisonError,
     ThreadError,
+    UnknownVariable(String),
+    InvalidConfig(Vec<ConfigDiagnostic>),
+    /// The child could not enter the namespace or switch to the user
+    SetupFailed(std::io::Error),
+    /// The command could not be executed
+    ExecFailed(std::io::Error),
+    /// The command ran longer than the timeout, and the container was killed
+    TimedOut,
 }
 
 impl ContainError {
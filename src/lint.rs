@@ -0,0 +1,420 @@
+use std::{fs, io::Error, path::Path};
+
+use log::error;
+
+use crate::contain_config::{CONFIG_KEYS, PROFILE_KEYS, RULE_KEYS};
+use crate::diagnostic::{ConfigDiagnostic, DiagnosticKind};
+use crate::env::ENV_KEYS;
+use crate::glob::{is_pattern, unescape};
+use crate::rule::{most_specific, Operation, RuleKind};
+use crate::{ConfigVars, ContainConfig, Source};
+
+/// Check a policy file for problems, without running anything.
+/// Variables in paths are expanded with `vars`,
+/// and the entries with unknown variables are reported along with everything else.
+/// Files that are included are merged, but only this file is checked for unknown keys.
+pub fn lint_file(filename: &str, vars: &ConfigVars) -> Result<Vec<ConfigDiagnostic>, Error> {
+    let mut diagnostics = unknown_keys(filename)?;
+    let mut config = match ContainConfig::from_file(filename) {
+        Ok(config) => config,
+        // A misspelled key may be why the config can't be read
+        Err(_) if !diagnostics.is_empty() => return Ok(diagnostics),
+        Err(e) => return Err(e),
+    };
+
+    // The other checks need the variables to be expanded,
+    // so the entries with unknown variables are left out of them
+    let unknown_vars: Vec<String> = path_entries(&config)
+        .chain(config.root.iter())
+        .filter(|path| vars.expand(path).is_err())
+        .cloned()
+        .collect();
+    for path in unknown_vars.iter() {
+        diagnostics.push(ConfigDiagnostic::new(
+            DiagnosticKind::UnknownVariable,
+            path,
+            &config,
+        ));
+    }
+    let known = |path: &String| !unknown_vars.contains(path);
+    for list in [
+        &mut config.files,
+        &mut config.dirs,
+        &mut config.rofiles,
+        &mut config.rodirs,
+        &mut config.deny_files,
+        &mut config.deny_dirs,
+    ] {
+        list.retain(known);
+    }
+    config.rules.retain(|rule| known(&rule.path));
+    config.root = config.root.filter(known);
+    config.expand_vars(vars).map_err(|e| {
+        error!("could not expand variables in {}: {}", filename, e);
+        Error::other(e.to_string())
+    })?;
+
+    diagnostics.extend(lint_config(&config));
+    Ok(diagnostics)
+}
+
+/// Check a config for problems:
+/// schemes that are both passed and sandboxed, invalid scheme names,
+/// paths that are not in the form "scheme:/path" or not in a sandboxed scheme,
+/// entries that a broader dir already allows,
+/// and read-only entries that another entry makes writable.
+pub fn lint_config(config: &ContainConfig) -> Vec<ConfigDiagnostic> {
+    let mut diagnostics = Vec::new();
+    let diagnostic = |kind, entry: &str| ConfigDiagnostic::new(kind, entry, config);
+
+    for scheme in config.pass_schemes.iter() {
+        if config.sandbox_schemes.contains(scheme) {
+            diagnostics.push(diagnostic(DiagnosticKind::PassedAndSandboxed, scheme));
+        }
+    }
+    for scheme in config
+        .pass_schemes
+        .iter()
+        .chain(config.sandbox_schemes.iter())
+    {
+        if scheme.contains([':', '/']) {
+            diagnostics.push(diagnostic(DiagnosticKind::InvalidScheme, scheme));
+        }
+    }
+
+    for path in config.root.iter().chain(path_entries(config)) {
+        if !path.contains(":/") {
+            diagnostics.push(diagnostic(DiagnosticKind::NotAPath, path));
+        } else if !config.in_sandbox(path) {
+            diagnostics.push(diagnostic(DiagnosticKind::NotSandboxed, path));
+        }
+    }
+
+    // Check each literal entry against the rule that would decide without it
+    let rules = config.effective_rules();
+    for (i, rule) in rules.iter().enumerate() {
+        if is_pattern(&rule.path) || !rule.path.contains(":/") {
+            continue;
+        }
+        let others: Vec<_> = rules
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, other)| other.clone())
+            .collect();
+        let path = unescape(&rule.path);
+
+        let writer = most_specific(&others, &path, &[Operation::Write]);
+        if let Some(writer) = writer {
+            if !rule.permits(&[Operation::Write])
+                && writer.permits(&[Operation::Write])
+                && writer.specificity() >= rule.specificity()
+            {
+                diagnostics.push(ConfigDiagnostic {
+                    related: Some(writer.path.clone()),
+                    source: rule.source.clone(),
+                    ..diagnostic(DiagnosticKind::Shadowed, &rule.path)
+                });
+                continue;
+            }
+        }
+
+        let broader = most_specific(&others, &path, &rule.allow);
+        if let Some(broader) = broader {
+            let same_ops = Operation::ALL
+                .iter()
+                .all(|op| broader.permits(&[*op]) == rule.permits(&[*op]));
+            if broader.kind == RuleKind::Dir
+                && broader.specificity() < rule.specificity()
+                && same_ops
+            {
+                diagnostics.push(ConfigDiagnostic {
+                    related: Some(broader.path.clone()),
+                    source: rule.source.clone(),
+                    ..diagnostic(DiagnosticKind::Redundant, &rule.path)
+                });
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// The diagnostics as a JSON array, one object per line
+pub fn diagnostics_to_json(diagnostics: &[ConfigDiagnostic]) -> String {
+    let objects: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| format!("  {}", diagnostic.to_json()))
+        .collect();
+    if objects.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[\n{}\n]", objects.join(",\n"))
+    }
+}
+
+// All the paths in the config, except root
+fn path_entries(config: &ContainConfig) -> impl Iterator<Item = &String> {
+    config
+        .files
+        .iter()
+        .chain(config.dirs.iter())
+        .chain(config.rofiles.iter())
+        .chain(config.rodirs.iter())
+        .chain(config.deny_files.iter())
+        .chain(config.deny_dirs.iter())
+        .chain(config.rules.iter().map(|rule| &rule.path))
+}
+
+// Find the keys in the file that are not part of the config.
+// Keys in the [profile] table and in each rule are checked too,
+// and are named e.g. "profile.name" or "rules.path".
+fn unknown_keys(filename: &str) -> Result<Vec<ConfigDiagnostic>, Error> {
+    let config_str = fs::read_to_string(filename)?;
+    let is_ron = Path::new(filename)
+        .extension()
+        .is_some_and(|ext| ext == "ron");
+    let mut keys: Vec<String> = Vec::new();
+    if is_ron {
+        let value: ron::Value = ron::from_str(&config_str).map_err(|e| {
+            error!("could not parse {}: {}", filename, e);
+            Error::other(e.to_string())
+        })?;
+        let ron_keys = |value: &ron::Value| -> Vec<String> {
+            match value {
+                ron::Value::Map(map) => map
+                    .keys()
+                    .filter_map(|key| match key {
+                        ron::Value::String(key) => Some(key.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            }
+        };
+        if let ron::Value::Map(map) = &value {
+            for (key, value) in map.iter() {
+                let ron::Value::String(key) = key else {
+                    continue;
+                };
+                keys.push(key.clone());
+                let nested: Vec<&ron::Value> = match value {
+                    ron::Value::Seq(items) if key == "rules" => items.iter().collect(),
+                    ron::Value::Map(_) if key == "profile" || key == "env" => vec![value],
+                    _ => vec![],
+                };
+                for item in nested {
+                    keys.extend(ron_keys(item).iter().map(|k| format!("{}.{}", key, k)));
+                }
+            }
+        }
+    } else {
+        let table: toml::Table = toml::from_str(&config_str).map_err(|e| {
+            error!("could not parse {}: {}", filename, e);
+            Error::other(e.to_string())
+        })?;
+        for (key, value) in table.iter() {
+            keys.push(key.clone());
+            let nested: Vec<&toml::Value> = match value {
+                toml::Value::Array(items) if key == "rules" => items.iter().collect(),
+                toml::Value::Table(_) if key == "profile" || key == "env" => vec![value],
+                _ => vec![],
+            };
+            for item in nested {
+                if let toml::Value::Table(table) = item {
+                    keys.extend(table.keys().map(|k| format!("{}.{}", key, k)));
+                }
+            }
+        }
+    }
+
+    let mut diagnostics: Vec<ConfigDiagnostic> = Vec::new();
+    for key in keys {
+        let (known, name) = match key.split_once('.') {
+            Some(("rules", name)) => (RULE_KEYS, name),
+            Some(("env", name)) => (ENV_KEYS, name),
+            Some((_, name)) => (PROFILE_KEYS, name),
+            None => (CONFIG_KEYS, key.as_str()),
+        };
+        if known.contains(&name) || diagnostics.iter().any(|d| d.entry == key) {
+            continue;
+        }
+        diagnostics.push(ConfigDiagnostic {
+            kind: DiagnosticKind::UnknownKey,
+            source: Some(Source {
+                file: filename.to_string(),
+                line: find_key_line(&config_str, name),
+            }),
+            entry: key,
+            related: None,
+        });
+    }
+    diagnostics.sort_by_key(|d| d.source.as_ref().and_then(|source| source.line));
+    Ok(diagnostics)
+}
+
+// Find the first line that sets the key, e.g. `key = ...` or `key: ...`
+fn find_key_line(config_str: &str, key: &str) -> Option<usize> {
+    config_str
+        .lines()
+        .position(|line| {
+            let line = line.trim_start().trim_start_matches(['(', '{', ',', ' ']);
+            match line.strip_prefix(key) {
+                Some(rest) => rest.trim_start().starts_with(['=', ':']),
+                None => false,
+            }
+        })
+        .map(|index| index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(diagnostics: &[ConfigDiagnostic]) -> Vec<(DiagnosticKind, &str)> {
+        diagnostics
+            .iter()
+            .map(|d| (d.kind, d.entry.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_scheme_and_path_problems() {
+        let config = ContainConfig {
+            pass_schemes: ["rand", "file", "tcp:"].map(String::from).to_vec(),
+            sandbox_schemes: vec!["file".to_string()],
+            files: ["file:/dev/null", "tmp/x", "tcp:/1.2.3.4"]
+                .map(String::from)
+                .to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            kinds(&lint_config(&config)),
+            [
+                (DiagnosticKind::PassedAndSandboxed, "file"),
+                (DiagnosticKind::InvalidScheme, "tcp:"),
+                (DiagnosticKind::NotAPath, "tmp/x"),
+                (DiagnosticKind::NotSandboxed, "tcp:/1.2.3.4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_redundant_and_shadowed_entries() {
+        let config = ContainConfig {
+            sandbox_schemes: vec!["file".to_string()],
+            files: vec!["file:/home/a/".to_string()],
+            dirs: ["file:/tmp", "file:/tmp/cache", "file:/home/**"]
+                .map(String::from)
+                .to_vec(),
+            rofiles: ["file:/tmp/ro", "file:/home/a"].map(String::from).to_vec(),
+            rodirs: ["file:/bin", "file:/bin/sub"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let diagnostics = lint_config(&config);
+        assert_eq!(
+            kinds(&diagnostics),
+            [
+                (DiagnosticKind::Redundant, "file:/tmp/cache"),
+                (DiagnosticKind::Shadowed, "file:/home/a"),
+                (DiagnosticKind::Redundant, "file:/bin/sub"),
+            ]
+        );
+        assert_eq!(diagnostics[1].related.as_deref(), Some("file:/home/a/"));
+    }
+
+    #[test]
+    fn finds_unknown_keys() {
+        let dir = std::env::temp_dir().join(format!("contain_test_lint_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let toml_file = dir.join("policy.toml");
+        fs::write(
+            &toml_file,
+            "pass_scheme = [\"rand\"]\n\
+             dirs = [\"${HOME}\"]\n\
+             [[rules]]\n\
+             path = \"file:/tmp\"\n\
+             alow = [\"read\"]\n\
+             [profile]\n\
+             name = \"x\"\n\
+             descrption = \"y\"\n\
+             [env]\n\
+             allow = [\"PATH\"]\n\
+             unset_all = true\n",
+        )
+        .unwrap();
+        let vars = ConfigVars {
+            home: "file:/home/alice".to_string(),
+            user: "alice".to_string(),
+            uid: 1000,
+            scheme: "file".to_string(),
+        };
+        let diagnostics = lint_file(&toml_file.to_string_lossy(), &vars).unwrap();
+        let unknown: Vec<(&str, Option<usize>)> = diagnostics
+            .iter()
+            .filter(|d| d.kind == DiagnosticKind::UnknownKey)
+            .map(|d| (d.entry.as_str(), d.source.as_ref().unwrap().line))
+            .collect();
+        assert_eq!(
+            unknown,
+            [
+                ("pass_scheme", Some(1)),
+                ("rules.alow", Some(5)),
+                ("profile.descrption", Some(8)),
+                ("env.unset_all", Some(11)),
+            ]
+        );
+
+        let ron_file = dir.join("policy.ron");
+        fs::write(&ron_file, "(\n  dirs: [\"file:/tmp\"],\n  dir: [],\n)\n").unwrap();
+        let diagnostics = lint_file(&ron_file.to_string_lossy(), &vars).unwrap();
+        assert_eq!(kinds(&diagnostics)[0], (DiagnosticKind::UnknownKey, "dir"));
+        assert_eq!(diagnostics[0].source.as_ref().unwrap().line, Some(3));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reports_unknown_variables_with_other_problems() {
+        let dir = std::env::temp_dir().join(format!("contain_test_vars_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let toml_file = dir.join("policy.toml");
+        fs::write(
+            &toml_file,
+            "sandbox_schemes = [\"file\"]\n\
+             dirs = [\"file:/tmp\", \"file:/tmp/cache\", \"file:/home/${USERNAME}\"]\n",
+        )
+        .unwrap();
+        let diagnostics = lint_file(&toml_file.to_string_lossy(), &ConfigVars::default()).unwrap();
+        assert_eq!(
+            kinds(&diagnostics),
+            [
+                (DiagnosticKind::UnknownVariable, "file:/home/${USERNAME}"),
+                (DiagnosticKind::Redundant, "file:/tmp/cache"),
+            ]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn writes_json() {
+        let diagnostic = ConfigDiagnostic {
+            kind: DiagnosticKind::Redundant,
+            entry: "file:/tmp/\"x\"".to_string(),
+            source: Some(Source {
+                file: "contain.toml".to_string(),
+                line: Some(3),
+            }),
+            related: Some("file:/tmp".to_string()),
+        };
+        assert_eq!(
+            diagnostics_to_json(&[diagnostic]),
+            "[\n  {\"kind\": \"redundant\", \"entry\": \"file:/tmp/\\\"x\\\"\", \
+             \"file\": \"contain.toml\", \"line\": 3, \"related\": \"file:/tmp\", \
+             \"message\": \"contain.toml:3: file:/tmp/\\\"x\\\": already allowed by file:/tmp\"}\n]"
+        );
+        assert_eq!(diagnostics_to_json(&[]), "[]");
+    }
+}
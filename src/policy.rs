@@ -0,0 +1,392 @@
+use std::fmt;
+use std::path::Path;
+
+use libredox::errno::*;
+use log::debug;
+use syscall::{Error, Result};
+
+use crate::contain_config::ContainConfig;
+use crate::rule::{most_specific, path_in_dir, path_in_scheme, Operation, Rule, RuleSet};
+
+/// Why a path is allowed or denied
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// A deny_files or deny_dirs entry matches the path
+    Denied(Rule),
+    /// The path is in the chroot
+    InRoot(String),
+    /// The scheme is passed through unchanged
+    PassScheme(String),
+    /// The scheme is neither passed nor sandboxed, so it is not in the namespace
+    NoScheme(String),
+    /// The most specific rule that matches the path
+    Rule(Rule),
+    /// No rule matches the path
+    NoRule,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reason::Denied(rule) => write!(f, "denied by {}", rule),
+            Reason::InRoot(root) => write!(f, "in the chroot {}", root),
+            Reason::PassScheme(scheme) => write!(f, "scheme {} is passed through", scheme),
+            Reason::NoScheme(scheme) => write!(f, "scheme {} is not in the namespace", scheme),
+            Reason::Rule(rule) if rule.allow.is_empty() => {
+                write!(f, "{}, which allows nothing", rule)
+            }
+            Reason::Rule(rule) => {
+                let allow: Vec<String> = rule.allow.iter().map(|op| op.to_string()).collect();
+                write!(f, "{}, which allows {}", rule, allow.join(", "))
+            }
+            Reason::NoRule => write!(f, "no rule matches"),
+        }
+    }
+}
+
+/// The outcome of checking a path against the config
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    /// The path that was checked, with a "/" after the scheme name
+    pub path: String,
+    pub allowed: bool,
+    pub reason: Reason,
+}
+
+impl Decision {
+    /// Ok if allowed, otherwise EPERM
+    pub fn result(&self) -> Result<()> {
+        if self.allowed {
+            Ok(())
+        } else {
+            Err(Error::new(EPERM))
+        }
+    }
+}
+
+/// How a path was resolved and checked, see `explain`
+#[derive(Debug)]
+pub struct Explanation {
+    /// The path with the scheme name, and the chroot if it applies
+    pub real_path: Option<String>,
+    /// The canonical path, which is the path that decides
+    pub checked_path: Option<String>,
+    /// The decision for the checked path. None if the path could not be checked.
+    pub decision: Option<Decision>,
+    /// The resolved path, or the error that the filter scheme returns
+    pub result: Result<String>,
+}
+
+/// Check an absolute path (starts with a scheme name) against the config,
+/// with the rules compiled from it by `ContainConfig::rule_set`.
+/// Deny rules are checked first, then the chroot and the pass schemes,
+/// then the most specific rule decides.
+pub fn check(config: &ContainConfig, rules: &RuleSet, path: &str, ops: &[Operation]) -> Decision {
+    debug!("check: {} for {:?}", path, ops);
+    // ensure there *is* a slash after the scheme name
+    let path = if let Some((scheme, subpath)) = path.split_once(':') {
+        if !scheme.contains('/') {
+            format!("{}:/{}", scheme, subpath.trim_start_matches('/'))
+        } else {
+            path.to_string()
+        }
+    } else {
+        path.to_string()
+    };
+    let (allowed, reason) = if let Some(rule) = rules.deny.iter().find(|rule| rule.matches(&path)) {
+        debug!("check: denied {} by rule {:?}", path, rule);
+        (false, Reason::Denied(rule.clone()))
+    } else if let Some(root) = config.root.as_ref().filter(|root| path_in_dir(&path, root)) {
+        debug!("check: is in root {}", path);
+        (true, Reason::InRoot(root.clone()))
+    } else if let Some(scheme) = config
+        .pass_schemes
+        .iter()
+        .find(|scheme| path_in_scheme(&path, scheme))
+    {
+        debug!("check: pass scheme {}", path);
+        (true, Reason::PassScheme(scheme.clone()))
+    } else {
+        match most_specific(&rules.allow, &path, ops) {
+            Some(rule) => {
+                let allowed = rule.permits(ops);
+                debug!("check: {} {} by rule {:?}", allowed, path, rule);
+                (allowed, Reason::Rule(rule.clone()))
+            }
+            None => {
+                debug!("check: failed {}, no matching rule", path);
+                (false, Reason::NoRule)
+            }
+        }
+    };
+    Decision {
+        path,
+        allowed,
+        reason,
+    }
+}
+
+/// Add the scheme name. See if it matches the filter.
+/// If it does, return the full path.
+/// If it does not match the filter, add the chroot (if any).
+/// The chrooted path is not checked against the filter as it will always succeed.
+pub(crate) fn real_path(
+    config: &ContainConfig,
+    rules: &RuleSet,
+    scheme: &str,
+    path: &str,
+    ops: &[Operation],
+) -> String {
+    let full_path = format!("{}:/{}", scheme, path.trim_start_matches('/'));
+    match config.root.as_ref() {
+        Some(root) if !check(config, rules, &full_path, ops).allowed => {
+            format!("{}/{}", root, path.trim_start_matches('/'))
+        }
+        _ => full_path,
+    }
+}
+
+/// Resolve a path in a sandboxed scheme the way the filter scheme does,
+/// recording each step.
+/// The path is checked, and if it is allowed, it is canonicalized and checked again,
+/// so a symlink can't be used to reach a denied path.
+/// If we are chroot'd, the name is prefixed with the root path if needed.
+/// If we are in "create" mode and the path does not exist yet,
+/// the parent dir is canonicalized and the filename added.
+pub fn resolve_explained(
+    config: &ContainConfig,
+    rules: &RuleSet,
+    scheme: &str,
+    path: &str,
+    ops: &[Operation],
+) -> Explanation {
+    let mut explanation = Explanation {
+        real_path: None,
+        checked_path: None,
+        decision: None,
+        result: Err(Error::new(EINVAL)),
+    };
+    if path.contains("../") || path.ends_with("..") {
+        debug!("path includes .. - {}", path);
+        return explanation;
+    }
+    if let Some(root) = config.root.as_ref() {
+        let full_path = format!("{}:/{}", scheme, path.trim_start_matches('/'));
+        if path_in_dir(&full_path, root) {
+            debug!("path includes root, but we are chroot'd, {}", path);
+            return explanation;
+        }
+    }
+    let real_path = real_path(config, rules, scheme, path, ops);
+    debug!("resolve {}", real_path);
+    explanation.real_path = Some(real_path.clone());
+    let canon_path = canonical_path(&real_path, ops.contains(&Operation::Create));
+    let canon_path = match canon_path {
+        Ok(canon_path) => canon_path,
+        Err(e) => {
+            explanation.result = Err(e);
+            return explanation;
+        }
+    };
+    let decision = check(config, rules, &canon_path, ops);
+    explanation.result = decision.result().map(|_| canon_path.clone());
+    explanation.checked_path = Some(canon_path);
+    explanation.decision = Some(decision);
+    explanation
+}
+
+// Canonicalize the path, following symlinks, so the path that is checked
+// is the one that will be opened. With `create`, a path that does not exist
+// yet is its canonical parent dir with the filename added,
+// and a symlink to a path that does not exist is resolved to that path.
+fn canonical_path(path: &str, create: bool) -> Result<String> {
+    let canon_path = match Path::new(path).canonicalize() {
+        Ok(canon_path) => canon_path,
+        Err(e) if create && e.kind() == std::io::ErrorKind::NotFound => {
+            if let Ok(target) = std::fs::read_link(path) {
+                // creating through the symlink creates its target
+                let target = Path::new(path)
+                    .parent()
+                    .ok_or(Error::new(ENOENT))?
+                    .join(target);
+                return canonical_path(target.to_str().ok_or(Error::new(EINVAL))?, create);
+            }
+            let filename = Path::new(path).file_name().ok_or(Error::new(EINVAL))?;
+            let mut canon_path = Path::new(path)
+                .parent()
+                .ok_or(Error::new(ENOENT))?
+                .canonicalize()
+                .map_err(|_| Error::new(ENOENT))?;
+            canon_path.push(filename);
+            canon_path
+        }
+        Err(_) => return Err(Error::new(if create { ENOENT } else { EPERM })),
+    };
+    canon_path
+        .to_str()
+        .ok_or(Error::new(EINVAL))
+        .map(|s| s.to_string())
+}
+
+/// Resolve a path in a sandboxed scheme, see `resolve_explained`
+pub(crate) fn resolve(
+    config: &ContainConfig,
+    rules: &RuleSet,
+    scheme: &str,
+    path: &str,
+    ops: &[Operation],
+) -> Result<String> {
+    resolve_explained(config, rules, scheme, path, ops).result
+}
+
+/// Explain how a path, e.g. "file:/home/user/x" or "/home/user/x",
+/// would be handled in the container, without starting one.
+/// A path without a scheme is in the "file" scheme.
+/// Paths in pass schemes are not resolved, and paths in other schemes
+/// that are not sandboxed do not exist in the container.
+pub fn explain(
+    config: &ContainConfig,
+    rules: &RuleSet,
+    path: &str,
+    ops: &[Operation],
+) -> Explanation {
+    let (scheme, subpath) = match path.split_once(':') {
+        Some((scheme, subpath)) if !scheme.contains('/') => (scheme, subpath),
+        _ => ("file", path),
+    };
+    if config.sandbox_schemes.iter().any(|s| s == scheme) {
+        return resolve_explained(config, rules, scheme, subpath, ops);
+    }
+    let full_path = format!("{}:/{}", scheme, subpath.trim_start_matches('/'));
+    let decision = if config.pass_schemes.iter().any(|s| s == scheme) {
+        check(config, rules, &full_path, ops)
+    } else {
+        Decision {
+            path: full_path.clone(),
+            allowed: false,
+            reason: Reason::NoScheme(scheme.to_string()),
+        }
+    };
+    Explanation {
+        real_path: Some(full_path.clone()),
+        checked_path: Some(full_path.clone()),
+        result: if decision.allowed {
+            Ok(full_path)
+        } else {
+            Err(Error::new(ENODEV))
+        },
+        decision: Some(decision),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ContainConfig {
+        let mut config = ContainConfig {
+            pass_schemes: vec!["rand".to_string()],
+            sandbox_schemes: vec!["file".to_string()],
+            ..Default::default()
+        };
+        config.add_dir("file:/tmp");
+        config.add_rodir("file:/bin");
+        config.add_deny_file("file:/tmp/secret");
+        config
+    }
+
+    #[test]
+    fn decides_with_reason() {
+        let config = config();
+        let rules = config.rule_set().unwrap();
+        let decision = check(&config, &rules, "file:/tmp/x", &[Operation::Write]);
+        assert!(decision.allowed);
+        assert!(matches!(decision.reason, Reason::Rule(ref rule) if rule.path == "file:/tmp"));
+
+        let decision = check(&config, &rules, "file:bin/ls", &[Operation::Write]);
+        assert_eq!(decision.path, "file:/bin/ls");
+        assert!(!decision.allowed);
+        assert!(matches!(decision.reason, Reason::Rule(ref rule) if rule.path == "file:/bin"));
+
+        let decision = check(&config, &rules, "file:/tmp/secret", &[Operation::Read]);
+        assert!(!decision.allowed);
+        assert!(matches!(decision.reason, Reason::Denied(_)));
+
+        assert_eq!(
+            check(&config, &rules, "file:/etc/passwd", &[Operation::Read]).reason,
+            Reason::NoRule
+        );
+        assert_eq!(
+            check(&config, &rules, "rand:", &[Operation::Read]).reason,
+            Reason::PassScheme("rand".to_string())
+        );
+    }
+
+    #[test]
+    fn explains_schemes() {
+        let config = config();
+        let rules = config.rule_set().unwrap();
+        let explanation = explain(&config, &rules, "rand:", &[Operation::Read]);
+        assert_eq!(explanation.result.unwrap(), "rand:/");
+
+        let explanation = explain(&config, &rules, "tcp:/1.2.3.4", &[Operation::Read]);
+        assert!(explanation.result.is_err());
+        assert_eq!(
+            explanation.decision.unwrap().reason,
+            Reason::NoScheme("tcp".to_string())
+        );
+
+        let explanation = explain(&config, &rules, "/tmp/../etc/passwd", &[Operation::Read]);
+        assert!(explanation.result.is_err());
+        assert!(explanation.decision.is_none());
+    }
+
+    #[test]
+    fn rewrites_for_chroot() {
+        let mut config = config();
+        config.add_chroot("file:/jail");
+        let rules = config.rule_set().unwrap();
+        assert_eq!(
+            real_path(&config, &rules, "file", "/etc/passwd", &[Operation::Read]),
+            "file:/jail/etc/passwd"
+        );
+        assert_eq!(
+            real_path(&config, &rules, "file", "/tmp/x", &[Operation::Read]),
+            "file:/tmp/x"
+        );
+    }
+
+    #[test]
+    fn denies_symlinks_to_denied_files() {
+        let dir = std::env::temp_dir().join(format!("contain_policy_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap().to_str().unwrap().to_string();
+        let secret = format!("{}/secret", dir);
+        let link = format!("{}/link", dir);
+        std::fs::write(&secret, "secret").unwrap();
+        std::os::unix::fs::symlink(&secret, &link).unwrap();
+
+        let mut config = ContainConfig::default();
+        config.add_dir(&dir);
+        config.add_deny_file(&secret);
+        let rules = config.rule_set().unwrap();
+        let ops = [Operation::Write, Operation::Create];
+        // creating through an existing symlink opens its target
+        let canon_path = canonical_path(&link, true).unwrap();
+        assert_eq!(canon_path, secret);
+        assert!(!check(&config, &rules, &canon_path, &ops).allowed);
+        // a new file is its parent with the filename added
+        let new_path = format!("{}/new", dir);
+        assert_eq!(canonical_path(&new_path, true).unwrap(), new_path);
+        assert!(check(&config, &rules, &new_path, &ops).allowed);
+        assert!(canonical_path(&new_path, false).is_err());
+        // so is the missing target of a symlink
+        let missing = format!("{}/missing", dir);
+        let dangling = format!("{}/dangling", dir);
+        std::os::unix::fs::symlink(&missing, &dangling).unwrap();
+        assert_eq!(canonical_path(&dangling, true).unwrap(), missing);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
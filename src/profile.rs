@@ -0,0 +1,258 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+
+use redox_users::{All, AllGroups};
+
+use crate::ContainConfig;
+
+/// Directory for named profiles, e.g. /etc/contain.d/profiles/shell.toml
+pub const PROFILE_DIR: &str = "/etc/contain.d/profiles";
+/// Directory for per-user configs, e.g. /etc/contain.d/users/alice.toml
+pub const USERS_DIR: &str = "/etc/contain.d/users";
+/// Directory for per-group configs, e.g. /etc/contain.d/groups/wheel.toml
+pub const GROUPS_DIR: &str = "/etc/contain.d/groups";
+
+/// Name and description of a profile, from its `[profile]` table
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+// Find NAME.toml or NAME.ron in the directory, if it exists
+fn find_file(dir: &str, name: &str) -> Result<Option<String>, Error> {
+    if name.is_empty() || name.contains(['/', ':']) || name.starts_with('.') {
+        error!("invalid name {}", name);
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid name {}", name),
+        ));
+    }
+    for ext in ["toml", "ron"] {
+        let filename = Path::new(dir).join(format!("{}.{}", name, ext));
+        if filename.is_file() {
+            return Ok(Some(filename.to_string_lossy().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Find the file for a named profile, NAME.toml or NAME.ron in the directory
+pub fn profile_file(dir: &str, name: &str) -> Result<String, Error> {
+    if let Some(filename) = find_file(dir, name)? {
+        return Ok(filename);
+    }
+    error!("profile {} not found in {}", name, dir);
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("profile {} not found in {}", name, dir),
+    ))
+}
+
+/// Read a named profile from the directory
+pub fn load_profile(dir: &str, name: &str) -> Result<ContainConfig, Error> {
+    let filename = profile_file(dir, name)?;
+    debug!("loading profile {} from {}", name, filename);
+    ContainConfig::from_file(&filename)
+}
+
+/// List the profiles in the directory, sorted by name.
+/// A profile without a `[profile]` table is named after its file.
+pub fn list_profiles(dir: &str) -> Result<Vec<ProfileInfo>, Error> {
+    let mut profiles = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = match (path.file_stem(), path.extension()) {
+            (Some(name), Some(ext)) if ext == "toml" || ext == "ron" => {
+                name.to_string_lossy().to_string()
+            }
+            _ => continue,
+        };
+        let config = ContainConfig::from_file(&path.to_string_lossy())?;
+        profiles.push(config.profile.unwrap_or(ProfileInfo {
+            name,
+            description: String::new(),
+        }));
+    }
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(profiles)
+}
+
+/// The directories that `select_config` reads from
+#[derive(Clone, Debug)]
+pub struct SelectDirs<'a> {
+    pub profiles: &'a str,
+    pub users: &'a str,
+    pub groups: &'a str,
+}
+
+impl Default for SelectDirs<'_> {
+    fn default() -> Self {
+        Self {
+            profiles: PROFILE_DIR,
+            users: USERS_DIR,
+            groups: GROUPS_DIR,
+        }
+    }
+}
+
+/// Add the settings for a user and their groups to the config.
+/// In order, merge the config file for each group, then the user's config file,
+/// then the profiles named for each group in `[groups]` and for the user in `[users]`.
+/// Use `ContainConfig::contains_uid` to check if the user should be contained.
+pub fn select_config(
+    mut config: ContainConfig,
+    dirs: &SelectDirs,
+    user: &str,
+    groups: &[String],
+) -> Result<ContainConfig, Error> {
+    for group in groups {
+        if let Some(filename) = find_file(dirs.groups, group)? {
+            debug!("adding config for group {} from {}", group, filename);
+            config.merge(ContainConfig::from_file(&filename)?);
+        }
+    }
+    if let Some(filename) = find_file(dirs.users, user)? {
+        debug!("adding config for user {} from {}", user, filename);
+        config.merge(ContainConfig::from_file(&filename)?);
+    }
+
+    let mut profiles: Vec<String> = Vec::new();
+    for group in groups {
+        profiles.extend(config.groups.get(group).into_iter().flatten().cloned());
+    }
+    profiles.extend(config.users.get(user).into_iter().flatten().cloned());
+    let mut added = Vec::new();
+    for name in profiles {
+        if !added.contains(&name) {
+            debug!("adding profile {} for user {}", name, user);
+            config.merge(load_profile(dirs.profiles, &name)?);
+            added.push(name);
+        }
+    }
+    Ok(config)
+}
+
+/// Select the config for a user, with the user's groups from /etc/group.
+/// See `select_config`.
+pub fn select_user_config<A>(
+    config: ContainConfig,
+    user: &redox_users::User<A>,
+) -> Result<ContainConfig, Error> {
+    let groups: Vec<String> = user_groups(user)?
+        .into_iter()
+        .map(|(group, _)| group)
+        .collect();
+    select_config(config, &SelectDirs::default(), &user.user, &groups)
+}
+
+// The name and gid of each group in /etc/group that the user is in,
+// including the user's primary group
+pub(crate) fn user_groups<A>(user: &redox_users::User<A>) -> Result<Vec<(String, usize)>, Error> {
+    let all_groups = AllGroups::new(redox_users::Config::default()).map_err(|e| {
+        error!("could not read groups: {}", e);
+        Error::other(e.to_string())
+    })?;
+    Ok(all_groups
+        .iter()
+        .filter(|group| group.gid == user.gid || group.users.contains(&user.user))
+        .map(|group| (group.group.clone(), group.gid))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHIPPED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/etc/contain.d/profiles");
+
+    #[test]
+    fn lists_shipped_profiles() {
+        let names: Vec<String> = list_profiles(SHIPPED)
+            .unwrap()
+            .into_iter()
+            .map(|profile| profile.name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "compiler",
+                "gui-app",
+                "network-client",
+                "offline-tool",
+                "shell"
+            ]
+        );
+    }
+
+    #[test]
+    fn loads_profile_by_name() {
+        let config = load_profile(SHIPPED, "network-client").unwrap();
+        assert_eq!(config.profile.unwrap().name, "network-client");
+        assert!(config.pass_schemes.contains(&"tcp".to_string()));
+        assert!(load_profile(SHIPPED, "missing").is_err());
+        assert!(load_profile(SHIPPED, "../contain").is_err());
+    }
+
+    #[test]
+    fn selects_user_and_group_configs() {
+        let dir = std::env::temp_dir().join(format!("contain_test_select_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("users")).unwrap();
+        fs::create_dir_all(dir.join("groups")).unwrap();
+        fs::write(
+            dir.join("groups/dev.toml"),
+            "dirs = [\"file:/src\"]\n[groups]\ndev = [\"compiler\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("users/alice.toml"),
+            "rodirs = [\"file:/alice\"]\n[users]\nalice = [\"compiler\", \"network-client\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("users/root.toml"), "contain_root = true\n").unwrap();
+        let users = dir.join("users");
+        let groups = dir.join("groups");
+        let dirs = SelectDirs {
+            profiles: SHIPPED,
+            users: &users.to_string_lossy(),
+            groups: &groups.to_string_lossy(),
+        };
+
+        let config = select_config(
+            ContainConfig::default(),
+            &dirs,
+            "alice",
+            &["dev".to_string()],
+        )
+        .unwrap();
+        assert!(config.dirs.contains(&"file:/src".to_string()));
+        assert!(config.rodirs.contains(&"file:/alice".to_string()));
+        assert!(config.pass_schemes.contains(&"tcp".to_string()));
+        // compiler is named twice but only added once
+        let toolchains = config
+            .rodirs
+            .iter()
+            .filter(|d| d.as_str() == "${HOME}/.cargo")
+            .count();
+        assert_eq!(toolchains, 1);
+        assert_eq!(config.profile.unwrap().name, "network-client");
+
+        let config = select_config(ContainConfig::default(), &dirs, "bob", &[]).unwrap();
+        assert!(config.dirs.is_empty() && config.pass_schemes.is_empty());
+        assert!(config.contains_uid(1000));
+        assert!(!config.contains_uid(0));
+
+        let config = select_config(ContainConfig::default(), &dirs, "root", &[]).unwrap();
+        assert!(config.contains_uid(0));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
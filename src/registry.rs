@@ -0,0 +1,181 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+
+use crate::env::EnvConfig;
+use crate::run_as::RunAs;
+use crate::runner::OnExit;
+
+/// Directory where running containers are recorded, one NAME.toml per container
+pub const RUN_DIR: &str = "/run/contain";
+
+/// A running container, as recorded by `contain run`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerInfo {
+    pub name: String,
+    /// The contain process that serves the namespace
+    pub pid: usize,
+    pub namespace: usize,
+    /// The command that was started in the container
+    pub command: String,
+    /// What happens to the processes left by a command when it exits
+    #[serde(default)]
+    pub on_exit: OnExit,
+    /// Seconds that each command may run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// The environment policy of the container, also used for commands run with `exec`
+    #[serde(default, skip_serializing_if = "EnvConfig::is_empty")]
+    pub env: EnvConfig,
+    /// The user that commands in the container run as, if not the caller
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_as: Option<RunAs>,
+}
+
+impl ContainerInfo {
+    /// True if the contain process is still running
+    pub fn is_running(&self) -> bool {
+        syscall::kill(self.pid, 0).is_ok()
+    }
+}
+
+// The file for a container name, rejecting names that could escape the directory
+fn container_file(dir: &str, name: &str) -> Result<PathBuf, Error> {
+    if name.is_empty() || name.contains(['/', ':']) || name.starts_with('.') {
+        error!("invalid container name {}", name);
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid container name {}", name),
+        ));
+    }
+    Ok(Path::new(dir).join(format!("{}.toml", name)))
+}
+
+fn read_info(filename: &Path) -> Result<ContainerInfo, Error> {
+    let info_str = fs::read_to_string(filename)?;
+    toml::from_str(&info_str).map_err(|e| {
+        error!("could not parse {}: {}", filename.display(), e);
+        Error::new(ErrorKind::InvalidData, e)
+    })
+}
+
+/// Record a container in the directory.
+/// Fails if a running container already has the name.
+pub fn register(dir: &str, info: &ContainerInfo) -> Result<(), Error> {
+    let filename = container_file(dir, &info.name)?;
+    if let Ok(existing) = read_info(&filename) {
+        if existing.is_running() {
+            error!("container {} is already running", info.name);
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("container {} is already running", info.name),
+            ));
+        }
+    }
+    fs::create_dir_all(dir)?;
+    let info_str = toml::to_string(info).map_err(Error::other)?;
+    debug!("register container {} in {}", info.name, dir);
+    fs::write(filename, info_str)
+}
+
+/// Remove a container from the directory
+pub fn unregister(dir: &str, name: &str) -> Result<(), Error> {
+    debug!("unregister container {} in {}", name, dir);
+    fs::remove_file(container_file(dir, name)?)
+}
+
+/// Find a container by name
+pub fn find_container(dir: &str, name: &str) -> Result<ContainerInfo, Error> {
+    let filename = container_file(dir, name)?;
+    if !filename.is_file() {
+        error!("container {} not found in {}", name, dir);
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("container {} not found", name),
+        ));
+    }
+    read_info(&filename)
+}
+
+/// The containers recorded in the directory, sorted by name.
+/// Files that can't be read are skipped. A missing directory has no containers.
+pub fn list_containers(dir: &str) -> Result<Vec<ContainerInfo>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut containers = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("toml")) {
+            continue;
+        }
+        match read_info(&path) {
+            Ok(info) => containers.push(info),
+            Err(e) => debug!("skipping {}: {}", path.display(), e),
+        }
+    }
+    containers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(containers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, pid: usize) -> ContainerInfo {
+        ContainerInfo {
+            name: name.to_string(),
+            pid,
+            namespace: pid + 1,
+            command: "ion".to_string(),
+            on_exit: OnExit::Wait,
+            timeout: Some(60),
+            env: EnvConfig {
+                allow: vec!["PATH".to_string()],
+                ..Default::default()
+            },
+            run_as: Some(RunAs {
+                user: "alice".to_string(),
+                uid: 1000,
+                gid: 1000,
+                groups: vec![100],
+                home: "file:/home/alice".to_string(),
+                shell: "/bin/ion".to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn registers_and_finds() {
+        let dir = std::env::temp_dir().join(format!("contain_test_run_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dir = dir.to_string_lossy();
+        assert!(list_containers(&dir).unwrap().is_empty());
+
+        register(&dir, &info("web", 10)).unwrap();
+        register(&dir, &info("build", 20)).unwrap();
+        assert_eq!(find_container(&dir, "web").unwrap(), info("web", 10));
+        let names: Vec<String> = list_containers(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["build", "web"]);
+
+        unregister(&dir, "web").unwrap();
+        assert_eq!(
+            find_container(&dir, "web").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        assert!(register(&dir, &info("../web", 30)).is_err());
+        let _ = fs::remove_dir_all(dir.as_ref());
+    }
+}
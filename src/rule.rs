@@ -0,0 +1,451 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use syscall::flag::{O_ACCMODE, O_APPEND, O_CREAT, O_RDWR, O_TRUNC, O_WRONLY};
+
+use crate::contain_config::Source;
+use crate::glob::{is_pattern, unescape, Pattern, PatternError};
+
+/// An operation that a rule can permit on a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Open for reading
+    Read,
+    /// Open for writing, anywhere in the file
+    Write,
+    /// Open for writing, but only at the end of the file (O_APPEND)
+    Append,
+    /// Create a file that does not exist (O_CREAT)
+    Create,
+    /// Discard the existing contents of a file (O_TRUNC)
+    Truncate,
+    /// Remove a file
+    Unlink,
+    /// Remove a directory
+    Rmdir,
+}
+
+impl Operation {
+    /// Every operation, as granted by `files` and `dirs`
+    pub const ALL: &'static [Operation] = &[
+        Operation::Read,
+        Operation::Write,
+        Operation::Append,
+        Operation::Create,
+        Operation::Truncate,
+        Operation::Unlink,
+        Operation::Rmdir,
+    ];
+
+    /// Read only, as granted by `rofiles` and `rodirs`
+    pub const READ_ONLY: &'static [Operation] = &[Operation::Read];
+
+    /// The operations needed to open a path with the given flags.
+    /// O_APPEND, O_CREAT and O_TRUNC modify the file, even if the
+    /// access mode is O_RDONLY, so they always count as writes.
+    pub fn for_open(flags: usize) -> Vec<Operation> {
+        let mut ops = Vec::new();
+        let write = if flags & O_APPEND != 0 {
+            Operation::Append
+        } else {
+            Operation::Write
+        };
+        match flags & O_ACCMODE {
+            O_RDWR => {
+                ops.push(Operation::Read);
+                ops.push(write);
+            }
+            O_WRONLY => ops.push(write),
+            // O_RDONLY, or no access mode at all
+            _ => {
+                ops.push(Operation::Read);
+                if write == Operation::Append {
+                    ops.push(Operation::Append);
+                }
+            }
+        }
+        if flags & O_CREAT != 0 {
+            ops.push(Operation::Create);
+        }
+        if flags & O_TRUNC != 0 {
+            ops.push(Operation::Truncate);
+        }
+        ops
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Append => "append",
+            Operation::Create => "create",
+            Operation::Truncate => "truncate",
+            Operation::Unlink => "unlink",
+            Operation::Rmdir => "rmdir",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Whether a rule applies to a single path or to a directory and its contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    /// Exact match of the path
+    #[default]
+    File,
+    /// The path and everything below it
+    Dir,
+}
+
+/// A path, and the operations that are permitted on it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    /// path to match, format "scheme:/dir/dir/file"
+    pub path: String,
+    /// match the exact path, or a directory and its contents
+    #[serde(default)]
+    pub kind: RuleKind,
+    /// operations that are permitted on matching paths
+    pub allow: Vec<Operation>,
+    /// where the rule was read from
+    #[serde(skip)]
+    pub source: Option<Source>,
+    /// the path prepared for matching, see `compile`
+    #[serde(skip)]
+    compiled: Option<Compiled>,
+}
+
+// A rule's path prepared for matching, so nothing is parsed on each check
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Compiled {
+    matcher: PathMatcher,
+    specificity: (usize, bool),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum PathMatcher {
+    // the path with its escapes removed
+    Literal(String),
+    Pattern(Pattern),
+}
+
+impl Compiled {
+    fn new(path: &str) -> Result<Self, PatternError> {
+        let matcher = if is_pattern(path) {
+            PathMatcher::Pattern(Pattern::new(path)?)
+        } else {
+            PathMatcher::Literal(unescape(path).into_owned())
+        };
+        let path = path.trim_end_matches('/');
+        let rest = path.split_once(':').map_or(path, |(_, rest)| rest);
+        let depth = rest
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .take_while(|component| !is_pattern(component))
+            .count();
+        let specificity = (depth, matches!(matcher, PathMatcher::Literal(_)));
+        Ok(Self {
+            matcher,
+            specificity,
+        })
+    }
+
+    fn matches(&self, kind: RuleKind, path: &str) -> bool {
+        match (&self.matcher, kind) {
+            (PathMatcher::Literal(rule_path), RuleKind::File) => same_path(path, rule_path),
+            (PathMatcher::Literal(rule_path), RuleKind::Dir) => path_in_dir(path, rule_path),
+            (PathMatcher::Pattern(pattern), RuleKind::File) => {
+                pattern.matches(path.trim_end_matches('/'))
+            }
+            (PathMatcher::Pattern(pattern), RuleKind::Dir) => pattern.matches_dir(path),
+        }
+    }
+}
+
+impl Rule {
+    /// A rule for an exact path
+    pub fn file(path: &str, allow: &[Operation]) -> Self {
+        Self {
+            path: path.to_string(),
+            kind: RuleKind::File,
+            allow: allow.to_vec(),
+            source: None,
+            compiled: None,
+        }
+    }
+
+    /// A rule for a directory and everything below it
+    pub fn dir(path: &str, allow: &[Operation]) -> Self {
+        Self {
+            path: path.to_string(),
+            kind: RuleKind::Dir,
+            allow: allow.to_vec(),
+            source: None,
+            compiled: None,
+        }
+    }
+
+    /// Prepare the path for matching: compile it if it is a glob pattern,
+    /// or remove its escapes if it is a literal path
+    pub fn compile(&mut self) -> Result<(), PatternError> {
+        self.compiled = Some(Compiled::new(&self.path)?);
+        Ok(())
+    }
+
+    /// Check if the rule applies to the path
+    pub fn matches(&self, path: &str) -> bool {
+        match &self.compiled {
+            Some(compiled) => compiled.matches(self.kind, path),
+            // If the rule was not compiled, compile it now
+            None => {
+                Compiled::new(&self.path).is_ok_and(|compiled| compiled.matches(self.kind, path))
+            }
+        }
+    }
+
+    /// How specific the rule is. A rule with more literal path components
+    /// before the first wildcard is more specific, a literal path is more specific
+    /// than a glob at the same depth, and an exact file is more specific
+    /// than a directory with the same path.
+    pub fn specificity(&self) -> (usize, bool, bool) {
+        let (depth, literal) = match &self.compiled {
+            Some(compiled) => compiled.specificity,
+            None => Compiled::new(&self.path).map_or((0, false), |compiled| compiled.specificity),
+        };
+        (depth, literal, self.kind == RuleKind::File)
+    }
+
+    /// Check if the rule permits all of the operations.
+    /// Permission to write implies permission to append.
+    pub fn permits(&self, ops: &[Operation]) -> bool {
+        ops.iter().all(|op| {
+            self.allow.contains(op)
+                || (*op == Operation::Append && self.allow.contains(&Operation::Write))
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            RuleKind::File => write!(f, "file {}", self.path)?,
+            RuleKind::Dir => write!(f, "dir {}", self.path)?,
+        }
+        if let Some(source) = self.source.as_ref() {
+            write!(f, " from {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rules from a config, compiled and ready to check paths against.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleSet {
+    /// rules that permit operations, the most specific match decides
+    pub allow: Vec<Rule>,
+    /// rules that permit nothing, checked before any allow
+    pub deny: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Compile the rules, failing on any invalid glob pattern
+    pub fn new(mut allow: Vec<Rule>, mut deny: Vec<Rule>) -> Result<Self, PatternError> {
+        for rule in allow.iter_mut().chain(deny.iter_mut()) {
+            rule.compile()?;
+        }
+        Ok(Self { allow, deny })
+    }
+}
+
+/// Find the most specific rule that matches the path, which decides the outcome.
+/// If several rules are equally specific, prefer one that permits the operations.
+pub(crate) fn most_specific<'a>(
+    rules: &'a [Rule],
+    path: &str,
+    ops: &[Operation],
+) -> Option<&'a Rule> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(path))
+        .max_by_key(|rule| (rule.specificity(), rule.permits(ops)))
+}
+
+/// Check if two paths are the same, ignoring trailing slashes.
+pub(crate) fn same_path(path: &str, other: &str) -> bool {
+    path.trim_end_matches('/') == other.trim_end_matches('/')
+}
+
+/// Check if the path is the directory, or is inside it.
+/// Only whole path components match, so "file:/tmp" does not contain "file:/tmpfoo".
+/// A directory of "scheme:" or "scheme:/" contains everything in the scheme.
+pub(crate) fn path_in_dir(path: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    match path.strip_prefix(dir) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || dir.ends_with(':'),
+        None => false,
+    }
+}
+
+/// Check if the path is in the scheme, e.g. "tcp:/1.2.3.4" is in "tcp" but not "tc".
+pub(crate) fn path_in_scheme(path: &str, scheme: &str) -> bool {
+    match path.split_once(':') {
+        Some((path_scheme, _)) => path_scheme == scheme.trim_end_matches(':'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_matches_whole_components() {
+        assert!(path_in_dir("file:/tmp", "file:/tmp"));
+        assert!(path_in_dir("file:/tmp/", "file:/tmp"));
+        assert!(path_in_dir("file:/tmp/foo", "file:/tmp"));
+        assert!(path_in_dir("file:/tmp/foo/bar", "file:/tmp"));
+        assert!(!path_in_dir("file:/tmpfoo", "file:/tmp"));
+        assert!(!path_in_dir("file:/tmpfoo/secret", "file:/tmp"));
+        assert!(!path_in_dir("file:/tmp.bak", "file:/tmp"));
+        assert!(!path_in_dir("file:/", "file:/tmp"));
+        assert!(!path_in_dir("other:/tmp/foo", "file:/tmp"));
+    }
+
+    #[test]
+    fn dir_with_trailing_slash() {
+        assert!(path_in_dir("file:/tmp", "file:/tmp/"));
+        assert!(path_in_dir("file:/tmp/foo", "file:/tmp/"));
+        assert!(path_in_dir("file:/tmp/foo", "file:/tmp//"));
+        assert!(!path_in_dir("file:/tmpfoo", "file:/tmp/"));
+    }
+
+    #[test]
+    fn dir_at_scheme_root() {
+        assert!(path_in_dir("file:/", "file:/"));
+        assert!(path_in_dir("file:/tmp", "file:/"));
+        assert!(path_in_dir("file:/tmp", "file:"));
+        assert!(!path_in_dir("filex:/tmp", "file:/"));
+        assert!(!path_in_dir("filex:/tmp", "file:"));
+    }
+
+    #[test]
+    fn file_matches_exactly() {
+        assert!(same_path("file:/etc/passwd", "file:/etc/passwd"));
+        assert!(same_path("file:/tmp/", "file:/tmp"));
+        assert!(!same_path("file:/etc/passwd2", "file:/etc/passwd"));
+        assert!(!same_path("file:/etc", "file:/etc/passwd"));
+    }
+
+    #[test]
+    fn scheme_matches_whole_name() {
+        assert!(path_in_scheme("tcp:", "tcp"));
+        assert!(path_in_scheme("tcp:/1.2.3.4:80", "tcp"));
+        assert!(path_in_scheme("tcp:/1.2.3.4:80", "tcp:"));
+        assert!(!path_in_scheme("tcpx:", "tcp"));
+        assert!(!path_in_scheme("tcpx:/1.2.3.4:80", "tcp"));
+        assert!(!path_in_scheme("tc:/1.2.3.4:80", "tcp"));
+        assert!(!path_in_scheme("tcp", "tcp"));
+    }
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let rules = [
+            Rule::dir("file:/home/alice", Operation::ALL),
+            Rule::dir("file:/home/alice/.ssh", Operation::READ_ONLY),
+            Rule::file("file:/home/alice/.ssh/known_hosts", Operation::ALL),
+            Rule::dir("file:/home/alice/.secret", &[]),
+        ];
+        let write = [Operation::Write];
+        let rule = most_specific(&rules, "file:/home/alice/notes", &write).unwrap();
+        assert_eq!(rule.path, "file:/home/alice");
+        assert!(rule.permits(&write));
+        let rule = most_specific(&rules, "file:/home/alice/.ssh/id_rsa", &write).unwrap();
+        assert_eq!(rule.path, "file:/home/alice/.ssh");
+        assert!(!rule.permits(&write));
+        assert!(rule.permits(&[Operation::Read]));
+        let rule = most_specific(&rules, "file:/home/alice/.ssh/known_hosts", &write).unwrap();
+        assert!(rule.permits(&write));
+        let rule = most_specific(&rules, "file:/home/alice/.secret/x", &[Operation::Read]).unwrap();
+        assert!(!rule.permits(&[Operation::Read]));
+        assert!(most_specific(&rules, "file:/home/bob", &write).is_none());
+    }
+
+    #[test]
+    fn file_beats_dir_with_same_path() {
+        let rules = [
+            Rule::dir("file:/tmp", Operation::ALL),
+            Rule::file("file:/tmp", Operation::READ_ONLY),
+        ];
+        let rule = most_specific(&rules, "file:/tmp", &[Operation::Write]).unwrap();
+        assert_eq!(rule.kind, RuleKind::File);
+        let rule = most_specific(&rules, "file:/tmp/foo", &[Operation::Write]).unwrap();
+        assert_eq!(rule.kind, RuleKind::Dir);
+    }
+
+    #[test]
+    fn equally_specific_rules_prefer_permitting() {
+        let rules = [
+            Rule::dir("file:/tmp", Operation::READ_ONLY),
+            Rule::dir("file:/tmp/", Operation::ALL),
+        ];
+        let rule = most_specific(&rules, "file:/tmp/foo", &[Operation::Write]).unwrap();
+        assert!(rule.permits(&[Operation::Write]));
+    }
+
+    #[test]
+    fn literal_rules_beat_shallower_globs() {
+        let rules = [
+            Rule::dir("file:/h*/**", Operation::ALL),
+            Rule::dir("file:/home", Operation::READ_ONLY),
+        ];
+        let rule = most_specific(&rules, "file:/home/x", &[Operation::Write]).unwrap();
+        assert_eq!(rule.path, "file:/home");
+        let rules = [
+            Rule::dir("file:/home/*", Operation::ALL),
+            Rule::dir("file:/home", Operation::READ_ONLY),
+        ];
+        let rule = most_specific(&rules, "file:/home/x", &[Operation::Write]).unwrap();
+        assert_eq!(rule.path, "file:/home");
+        let rules = [
+            Rule::dir("file:/home/*/.ssh", &[]),
+            Rule::dir("file:/home/alice", Operation::ALL),
+        ];
+        let rule = most_specific(&rules, "file:/home/alice/.ssh/id_rsa", &[Operation::Read]);
+        assert_eq!(rule.unwrap().path, "file:/home/alice");
+    }
+
+    #[test]
+    fn rules_match_globs() {
+        let mut bin = Rule::file("file:/bin/*", Operation::READ_ONLY);
+        bin.compile().unwrap();
+        assert!(bin.matches("file:/bin/ls"));
+        assert!(!bin.matches("file:/bin/sub/ls"));
+        let mut home = Rule::dir("file:/home/*/.cache", Operation::ALL);
+        home.compile().unwrap();
+        assert!(home.matches("file:/home/alice/.cache/x"));
+        assert!(!home.matches("file:/home/alice/.config"));
+        // not compiled, so compiled on demand
+        let libs = Rule::file("file:/lib/**/*.so", Operation::READ_ONLY);
+        assert!(libs.matches("file:/lib/x86_64/libc.so"));
+        assert!(Rule::file("file:/x/[", &[]).compile().is_err());
+        let escaped = Rule::dir("file:/data/\\[build]", Operation::ALL);
+        assert!(escaped.matches("file:/data/[build]/x"));
+        assert!(!escaped.matches("file:/data/b"));
+    }
+
+    #[test]
+    fn rules_use_component_matching() {
+        let dir = Rule::dir("file:/home/user/", Operation::ALL);
+        assert!(dir.matches("file:/home/user"));
+        assert!(dir.matches("file:/home/user/.profile"));
+        assert!(!dir.matches("file:/home/user2/.profile"));
+        let file = Rule::file("file:/etc/hostname", Operation::READ_ONLY);
+        assert!(file.matches("file:/etc/hostname"));
+        assert!(!file.matches("file:/etc/hostname.bak"));
+        assert!(!file.matches("file:/etc/hostname/x"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::Error;
+use std::process::Command;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::profile::user_groups;
+
+/// The user that the contained command runs as, see `run_in_namespace`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunAs {
+    /// The login name
+    pub user: String,
+    pub uid: usize,
+    pub gid: usize,
+    /// The supplementary groups
+    pub groups: Vec<usize>,
+    /// The home directory, including the scheme
+    pub home: String,
+    pub shell: String,
+}
+
+impl RunAs {
+    /// The user, with their groups from /etc/group
+    pub fn from_user<A>(user: &redox_users::User<A>) -> Result<Self, Error> {
+        let groups = user_groups(user)?
+            .into_iter()
+            .map(|(_, gid)| gid)
+            .filter(|gid| *gid != user.gid)
+            .collect();
+        Ok(Self {
+            user: user.user.clone(),
+            uid: user.uid,
+            gid: user.gid,
+            groups,
+            home: user.home.clone(),
+            shell: user.shell.clone(),
+        })
+    }
+
+    /// Set HOME, USER, LOGNAME and SHELL for the command
+    pub fn set_env(&self, command: &mut Command) {
+        command
+            .env("HOME", &self.home)
+            .env("USER", &self.user)
+            .env("LOGNAME", &self.user)
+            .env("SHELL", &self.shell);
+    }
+
+    /// The ids to switch to in the forked child, see `SwitchUser`
+    pub(crate) fn switch_user(&self) -> SwitchUser {
+        debug!(
+            "switch to uid {} gid {} groups {:?}",
+            self.uid, self.gid, self.groups
+        );
+        SwitchUser {
+            uid: self.uid as libc::uid_t,
+            gid: self.gid as libc::gid_t,
+            groups: self.groups.iter().map(|gid| *gid as libc::gid_t).collect(),
+        }
+    }
+}
+
+/// The user's ids, prepared before `fork` so that switching to them in the
+/// child doesn't allocate or log, which could deadlock in a multithreaded process.
+pub(crate) struct SwitchUser {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    groups: Vec<libc::gid_t>,
+}
+
+impl SwitchUser {
+    /// Switch this process to the user's groups, gid and uid, in that order,
+    /// since changing the uid gives up the right to change the others.
+    /// Called in the forked child before `exec`, so only async-signal-safe calls here.
+    pub(crate) fn switch(&self) -> Result<(), Error> {
+        set_groups(&self.groups)?;
+        if unsafe { libc::setgid(self.gid) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        if unsafe { libc::setuid(self.uid) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+// Redox has no supplementary groups in the kernel, only the uid and gid are checked
+#[cfg(target_os = "redox")]
+fn set_groups(_groups: &[libc::gid_t]) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "redox"))]
+fn set_groups(groups: &[libc::gid_t]) -> Result<(), Error> {
+    if unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_login_variables() {
+        let run_as = RunAs {
+            user: "alice".to_string(),
+            uid: 1000,
+            gid: 1000,
+            groups: vec![],
+            home: "file:/home/alice".to_string(),
+            shell: "/bin/ion".to_string(),
+        };
+        let mut command = Command::new("true");
+        run_as.set_env(&mut command);
+        let mut envs: Vec<_> = command
+            .get_envs()
+            .filter_map(|(name, value)| Some((name.to_str()?, value?.to_str()?)))
+            .collect();
+        envs.sort();
+        assert_eq!(
+            envs,
+            [
+                ("HOME", "file:/home/alice"),
+                ("LOGNAME", "alice"),
+                ("SHELL", "/bin/ion"),
+                ("USER", "alice")
+            ]
+        );
+    }
+}
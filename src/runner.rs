@@ -1,47 +1,261 @@
 use std::{
-    os::unix::process::CommandExt,
-    process::{exit, Command},
+    ffi::CString,
+    fmt,
+    fs::{self, File},
+    io::Read,
+    os::unix::{
+        ffi::OsStrExt,
+        io::FromRawFd,
+        process::{CommandExt, ExitStatusExt},
+    },
+    process::{Child, Command, ExitStatus},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use libredox::call::waitpid;
 use libredox::error::{Error, EIO};
 use libredox::flag::O_RDONLY;
 use libredox::Fd;
-use log::{debug, error};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
 
-use crate::{ContainConfig, ContainError, ContainResult, ContainThread, CONTAIN_EXEC_FAIL_EXIT};
+use crate::diagnostic::{ConfigDiagnostic, DiagnosticKind};
+use crate::env::EnvConfig;
+use crate::registry::{register, unregister, ContainerInfo};
+use crate::run_as::{RunAs, SwitchUser};
+use crate::signals::SignalForwarder;
+use crate::{ContainConfig, ContainError, ContainResult, ContainThread};
+
+// The exit code of a child that failed before executing the command
+const CHILD_FAIL_EXIT: libc::c_int = 127;
+
+/// How long the container's processes get to exit after SIGTERM, before SIGKILL
+pub const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+// How often to check if the container's processes have exited
+const GROUP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What to do with the processes that are left in the container
+/// when the command exits, the `on_exit` config key.
+/// The processes are found by the command's process group, and, for the container's
+/// own command, by the namespace, so a process that leaves the group
+/// with `setsid` or `setpgid` is still waited for or killed.
+/// That needs the namespace's processes to be listed in `sys:context`,
+/// otherwise only the group is reached.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnExit {
+    /// Send them SIGTERM, then SIGKILL after `KILL_GRACE_PERIOD`
+    #[default]
+    Kill,
+    /// Wait for them to exit
+    Wait,
+    /// Leave them running, without the sandboxed schemes once contain exits.
+    /// contain warns about the ones that left the group.
+    Detach,
+}
+
+/// How the command in the container exited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContainExit {
+    /// The exit code, if the command exited
+    pub code: Option<i32>,
+    /// The signal that terminated the command, if it was killed
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+}
+
+impl ContainExit {
+    /// Decode a `waitpid` status
+    pub fn from_status(status: i32) -> Self {
+        let status = status as usize;
+        if syscall::wifsignaled(status) {
+            Self {
+                code: None,
+                signal: Some(syscall::wtermsig(status) as i32),
+                core_dumped: syscall::wcoredump(status),
+            }
+        } else {
+            Self {
+                code: Some(syscall::wexitstatus(status) as i32),
+                signal: None,
+                core_dumped: false,
+            }
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// The exit code the way a shell reports it, 128 + the signal if there was one
+    pub fn exit_code(&self) -> i32 {
+        match (self.code, self.signal) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1,
+        }
+    }
+}
+
+impl From<ExitStatus> for ContainExit {
+    fn from(status: ExitStatus) -> Self {
+        Self {
+            code: status.code(),
+            signal: status.signal(),
+            core_dumped: status.core_dumped(),
+        }
+    }
+}
+
+impl fmt::Display for ContainExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit code {}", code)?,
+            (None, Some(signal)) => write!(f, "killed by signal {}", signal)?,
+            (None, None) => write!(f, "unknown exit status")?,
+        }
+        if self.core_dumped {
+            write!(f, " (core dumped)")?;
+        }
+        Ok(())
+    }
+}
 
 /// Spawn and execute a command with no namespace changes.
 /// Used to execute a root shell.
-pub fn run_not_contained(mut command: Command) -> ContainResult<i32> {
+pub fn run_not_contained(mut command: Command) -> ContainResult<ContainExit> {
     let mut child = command.spawn().map_err(|e| {
         error!("failed to spawn uncontained command");
         ContainError::io_error(e)
     })?;
-    match child
-        .wait()
-        .map_err(|e| {
-            error!("failed to wait on uncontained command");
-            ContainError::io_error(e)
-        })?
-        .code()
-    {
-        Some(code) => Ok(code),
-        None => Ok(1),
-    }
+    let status = child.wait().map_err(|e| {
+        error!("failed to wait on uncontained command");
+        ContainError::io_error(e)
+    })?;
+    Ok(status.into())
 }
 
 /// Create a filtered scheme thread in a new namespace which will provide
 /// sandboxed proxy schemes as described in the config.
 /// Then fork and execute a command in that sandboxed namespace.
-pub fn run_contained(config: ContainConfig, command: Command) -> ContainResult<i32> {
-    let config = validate_config(config)?;
+/// The config is validated first, and the entries it drops are logged as warnings.
+pub fn run_contained(config: ContainConfig, command: Command) -> ContainResult<ContainExit> {
+    let config = validate_and_warn(config)?;
+    run(config, command, None, None, None)
+}
+
+/// Like `run_contained`, but record the container in `dir` while the command runs,
+/// so it can be found by `contain ps`, `exec` and `stop`.
+/// Without a name, the container is named after the pid of this process,
+/// and it still runs if it can't be recorded.
+/// With `run_as`, the command runs as that user, see `run_in_namespace`.
+/// The config is used as it is, so validate it first with `validate_config`
+/// and report the diagnostics.
+pub fn run_registered(
+    config: ContainConfig,
+    command: Command,
+    dir: &str,
+    name: Option<&str>,
+    run_as: Option<&RunAs>,
+) -> ContainResult<ContainExit> {
+    run(config, command, Some(dir), name, run_as)
+}
+
+// Create the namespace for a validated config and run the command in it,
+// recording the container in `dir` if there is one
+fn run(
+    config: ContainConfig,
+    mut command: Command,
+    dir: Option<&str>,
+    name: Option<&str>,
+    run_as: Option<&RunAs>,
+) -> ContainResult<ContainExit> {
+    let pid = getpid()?;
+    let named = name.is_some();
+    let name = name.map(String::from).unwrap_or_else(|| pid.to_string());
+    set_env(&mut command, &config.env, &name);
+    let env = config.env.clone();
+    let on_exit = config.on_exit.unwrap_or_default();
+    let timeout = config.timeout.map(Duration::from_secs);
     let contain_thread = ContainThread::new(config).map_err(|e| {
         error!("could not get contain thread: {}", e);
         e
     })?;
 
-    run_in_namespace(command, contain_thread.namespace())
+    let mut command_line = vec![command.get_program().to_string_lossy().to_string()];
+    command_line.extend(
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string()),
+    );
+    let info = ContainerInfo {
+        name,
+        pid,
+        namespace: contain_thread.namespace(),
+        command: command_line.join(" "),
+        on_exit,
+        timeout: timeout.map(|timeout| timeout.as_secs()),
+        env,
+        run_as: run_as.cloned(),
+    };
+    let registered = match dir {
+        Some(dir) => match register(dir, &info) {
+            Ok(()) => Some(dir),
+            Err(e) if !named => {
+                warn!("could not record container {} in {}: {}", info.name, dir, e);
+                None
+            }
+            Err(e) => return Err(ContainError::io_error(e)),
+        },
+        None => None,
+    };
+
+    let result = run_in_namespace(
+        command,
+        contain_thread.namespace(),
+        run_as,
+        on_exit,
+        timeout,
+    );
+
+    if let Some(dir) = registered {
+        let _ = unregister(dir, &info.name);
+    }
+    result
+}
+
+/// Run a command in a running container that was recorded by `run_registered`,
+/// the way the container's own command runs:
+/// with its environment policy, as its user, and with its `on_exit` and timeout.
+/// `on_exit` only applies to the command's own process group,
+/// the container's other processes are left to its own command.
+pub fn exec_registered(info: &ContainerInfo, mut command: Command) -> ContainResult<ContainExit> {
+    set_env(&mut command, &info.env, &info.name);
+    // the container's other processes are not this command's to end
+    run_command(
+        command,
+        info.namespace,
+        info.run_as.as_ref(),
+        info.on_exit,
+        info.timeout.map(Duration::from_secs),
+        false,
+    )
+}
+
+// The pid of this process, which names a container that has no name
+pub(crate) fn getpid() -> ContainResult<usize> {
+    syscall::getpid().map_err(|e| {
+        error!("could not get pid: {}", e);
+        ContainError::syscall_error(e)
+    })
+}
+
+// Apply the container's environment policy to the command,
+// and let it know that it is contained, and in which container
+pub(crate) fn set_env(command: &mut Command, env: &EnvConfig, name: &str) {
+    env.apply(command);
+    command.env("CONTAIN", "1").env("CONTAIN_NAME", name);
 }
 
 /// List all schemes.
@@ -73,124 +287,777 @@ fn list_schemes() -> ContainResult<Vec<String>> {
 
 /// Validate the config.
 /// Remove duplicate schemes and schemes that are not available.
+/// Remove a scheme name with ':' or '/' in it, and a path that is not "scheme:/path".
 /// Remove a filtered file or directory if it is not a in sandboxed scheme.
-fn validate_config(mut config: ContainConfig) -> ContainResult<ContainConfig> {
+/// Compile the rules, including any glob patterns.
+/// Each entry that is removed is returned as a warning,
+/// or is an error if the config is `strict`.
+pub fn validate_config(
+    config: ContainConfig,
+) -> ContainResult<(ContainConfig, Vec<ConfigDiagnostic>)> {
     let schemes = list_schemes()?;
     debug!("schemes: {:?}", schemes);
-    // quietly remove duplicates and ignore non-existent schemes
-    config.pass_schemes.sort();
-    config.pass_schemes.dedup();
-    config.pass_schemes.retain(|scheme| {
-        let is_known = schemes.contains(scheme);
-        if !is_known {
-            debug!("{scheme} is not recognized");
-        }
-        is_known
-    });
-    config.sandbox_schemes.sort();
-    config.sandbox_schemes.dedup();
-    config.sandbox_schemes.retain(|scheme| {
-        let is_known = schemes.contains(scheme);
-        if !is_known {
-            debug!("{scheme} is not recognized");
-        }
-        is_known
-    });
+    validate_with_schemes(config, &schemes)
+}
+
+// Validate the config for a caller that has no way to show the diagnostics,
+// so log each of them as a warning
+pub(crate) fn validate_and_warn(config: ContainConfig) -> ContainResult<ContainConfig> {
+    let (config, diagnostics) = validate_config(config)?;
+    for diagnostic in diagnostics.iter() {
+        warn!("{}", diagnostic);
+    }
+    Ok(config)
+}
+
+// The entry lists that are checked by validate_config
+const SCHEME_LISTS: [fn(&mut ContainConfig) -> &mut Vec<String>; 2] =
+    [|c| &mut c.pass_schemes, |c| &mut c.sandbox_schemes];
+const PATH_LISTS: [fn(&mut ContainConfig) -> &mut Vec<String>; 6] = [
+    |c| &mut c.files,
+    |c| &mut c.dirs,
+    |c| &mut c.rofiles,
+    |c| &mut c.rodirs,
+    |c| &mut c.deny_files,
+    |c| &mut c.deny_dirs,
+];
+
+fn validate_with_schemes(
+    mut config: ContainConfig,
+    schemes: &[String],
+) -> ContainResult<(ContainConfig, Vec<ConfigDiagnostic>)> {
+    let mut diagnostics = Vec::new();
+    // remove duplicates and non-existent schemes
+    for list in SCHEME_LISTS {
+        let mut entries = std::mem::take(list(&mut config));
+        entries.sort();
+        entries.dedup();
+        entries.retain(|scheme| {
+            if scheme.contains([':', '/']) {
+                debug!("{scheme} is not a scheme name");
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::InvalidScheme,
+                    scheme,
+                    &config,
+                ));
+                return false;
+            }
+            let is_known = schemes.contains(scheme);
+            if !is_known {
+                debug!("{scheme} is not recognized");
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::UnknownScheme,
+                    scheme,
+                    &config,
+                ));
+            }
+            is_known
+        });
+        *list(&mut config) = entries;
+    }
     // Error if the chroot is not a sandboxed scheme
-    if config.root.is_some()
-        && !config.sandbox_schemes.iter().any(|scheme| {
-            config
-                .root
-                .as_ref()
-                .unwrap()
-                .starts_with(&format!("{}:", scheme))
-        })
+    if config
+        .root
+        .as_ref()
+        .is_some_and(|root| !config.in_sandbox(root))
     {
         error!("root {} is not in a sandboxed scheme", config.root.unwrap());
         return Err(ContainError::ConfigError);
     }
-    // Quietly remove any files or directories that are not
+    // Remove any files or directories that are not
     // in a sandboxed scheme
-    config.files.sort();
-    config.files.dedup();
-    config.files.retain(|f| {
-        config
-            .sandbox_schemes
-            .iter()
-            .any(|scheme| f.starts_with(&format!("{scheme}:")))
-    });
-    config.dirs.sort();
-    config.dirs.dedup();
-    config.dirs.retain(|d| {
-        config
-            .sandbox_schemes
-            .iter()
-            .any(|scheme| d.starts_with(&format!("{scheme}:")))
-    });
-    config.rofiles.sort();
-    config.rofiles.dedup();
-    config.rofiles.retain(|f| {
-        config
-            .sandbox_schemes
-            .iter()
-            .any(|scheme| f.starts_with(&format!("{scheme}:")))
-    });
-    config.rodirs.sort();
-    config.rodirs.dedup();
-    config.rodirs.retain(|d| {
-        config
-            .sandbox_schemes
-            .iter()
-            .any(|scheme| d.starts_with(&format!("{scheme}:")))
+    for list in PATH_LISTS {
+        let mut entries = std::mem::take(list(&mut config));
+        entries.sort();
+        entries.dedup();
+        entries.retain(|path| {
+            if !path.contains(":/") {
+                debug!("{path} is not in the form scheme:/path");
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::NotAPath,
+                    path,
+                    &config,
+                ));
+                return false;
+            }
+            let is_sandboxed = config.in_sandbox(path);
+            if !is_sandboxed {
+                debug!("{path} is not in a sandboxed scheme");
+                diagnostics.push(ConfigDiagnostic::new(
+                    DiagnosticKind::NotSandboxed,
+                    path,
+                    &config,
+                ));
+            }
+            is_sandboxed
+        });
+        *list(&mut config) = entries;
+    }
+    let mut rules = std::mem::take(&mut config.rules);
+    rules.retain(|rule| {
+        if !rule.path.contains(":/") {
+            debug!("{} is not in the form scheme:/path", rule.path);
+            diagnostics.push(ConfigDiagnostic::new(
+                DiagnosticKind::NotAPath,
+                &rule.path,
+                &config,
+            ));
+            return false;
+        }
+        let is_sandboxed = config.in_sandbox(&rule.path);
+        if !is_sandboxed {
+            debug!("{} is not in a sandboxed scheme", rule.path);
+            diagnostics.push(ConfigDiagnostic::new(
+                DiagnosticKind::NotSandboxed,
+                &rule.path,
+                &config,
+            ));
+        }
+        is_sandboxed
     });
+    config.rules = rules;
+
+    if config.strict == Some(true) && !diagnostics.is_empty() {
+        for diagnostic in diagnostics.iter() {
+            error!("{}", diagnostic);
+        }
+        return Err(ContainError::InvalidConfig(diagnostics));
+    }
+
+    config.rule_set().map_err(|e| {
+        error!("{}", e);
+        ContainError::ConfigError
+    })?;
+    config.env.allow_list().map_err(|e| {
+        error!("env: {}", e);
+        ContainError::ConfigError
+    })?;
     debug!("validated: {:?}", &config);
-    Ok(config)
+    Ok((config, diagnostics))
 }
 
-/// After the new namespace has been created, run the command in that namespace.
-/// Once the command completes, terminate the namesapce thread.
-pub fn run_in_namespace(mut command: Command, namespace: usize) -> ContainResult<i32> {
+// What the child failed at, the first byte of its report
+const REPORT_SETUP: u8 = 0;
+const REPORT_EXEC: u8 = 1;
+
+// In the child, report an error to the parent over the pipe.
+// This runs between fork and exec, so only async-signal-safe calls here.
+fn report_failure(report_fd: libc::c_int, stage: u8, e: std::io::Error) -> std::io::Error {
+    let errno = e.raw_os_error().unwrap_or(libc::EINVAL).to_ne_bytes();
+    let report = [stage, errno[0], errno[1], errno[2], errno[3]];
+    unsafe {
+        libc::write(
+            report_fd,
+            report.as_ptr() as *const libc::c_void,
+            report.len(),
+        )
+    };
+    e
+}
+
+// Read the child's report. The pipe is closed on exec, so nothing is read
+// if the child got as far as executing the command.
+// Only the first report counts, the child may fail again on its way out.
+fn read_report(report_fd: libc::c_int) -> Option<ContainError> {
+    let mut report = Vec::new();
+    let mut pipe = unsafe { File::from_raw_fd(report_fd) };
+    if let Err(e) = pipe.read_to_end(&mut report) {
+        error!("could not read the child's report: {}", e);
+        return None;
+    }
+    let errno = i32::from_ne_bytes(report.get(1..5)?.try_into().ok()?);
+    let e = std::io::Error::from_raw_os_error(errno);
+    match report[0] {
+        REPORT_EXEC => Some(ContainError::ExecFailed(e)),
+        _ => Some(ContainError::SetupFailed(e)),
+    }
+}
+
+// A close-on-exec pipe for the child's report, (read, write)
+fn report_pipe() -> ContainResult<(libc::c_int, libc::c_int)> {
+    let mut report_fds = [0; 2];
+    if unsafe { libc::pipe2(report_fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        let e = std::io::Error::last_os_error();
+        error!("contain: pipe failed, {}", e);
+        return Err(ContainError::io_error(e));
+    }
+    Ok((report_fds[0], report_fds[1]))
+}
+
+// The last steps in the child before exec, in this order:
+// enter the namespace if there is one, change to `cwd` inside it, switch user,
+// and make the child's process group the foreground of the terminal if `foreground`.
+// Failures are reported over `report_fd`. Only async-signal-safe calls here.
+fn child_setup(
+    namespace: Option<usize>,
+    cwd: Option<CString>,
+    switch_user: Option<SwitchUser>,
+    foreground: bool,
+    report_fd: libc::c_int,
+) -> impl FnMut() -> std::io::Result<()> + Send + Sync + 'static {
+    move || {
+        if let Some(namespace) = namespace {
+            if let Err(e) = syscall::setrens(namespace, namespace) {
+                return Err(report_failure(
+                    report_fd,
+                    REPORT_SETUP,
+                    std::io::Error::from_raw_os_error(e.errno),
+                ));
+            }
+        }
+        if let Some(cwd) = cwd.as_ref() {
+            if unsafe { libc::chdir(cwd.as_ptr()) } == -1 {
+                let e = std::io::Error::last_os_error();
+                return Err(report_failure(report_fd, REPORT_SETUP, e));
+            }
+        }
+        if let Some(switch_user) = switch_user.as_ref() {
+            switch_user
+                .switch()
+                .map_err(|e| report_failure(report_fd, REPORT_SETUP, e))?;
+        }
+        if foreground {
+            give_terminal(unsafe { libc::getpgrp() });
+        }
+        Ok(())
+    }
+}
+
+// True if this process is in the foreground of the terminal on stdin
+fn in_foreground() -> bool {
+    unsafe { libc::isatty(0) == 1 && libc::tcgetpgrp(0) == libc::getpgrp() }
+}
+
+// Make the process group the foreground of the terminal on stdin,
+// without being stopped for doing it from the background.
+// Also called in the child, so only async-signal-safe calls here.
+fn give_terminal(pgid: libc::pid_t) {
+    unsafe {
+        let ttou = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(0, pgid);
+        libc::signal(libc::SIGTTOU, ttou);
+    }
+}
+
+// True if any process is left in the process group
+fn group_alive(pgid: usize) -> bool {
+    unsafe { libc::kill(-(pgid as libc::pid_t), 0) == 0 }
+}
+
+pub(crate) fn kill_group(pgid: usize, signal: libc::c_int) -> std::io::Result<()> {
+    if unsafe { libc::kill(-(pgid as libc::pid_t), signal) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Start a thread that ends the process group unless `done` is sent or dropped
+// before the timeout: SIGTERM, then SIGKILL after `KILL_GRACE_PERIOD`.
+// The thread returns true if the time ran out.
+pub(crate) fn start_timer(pgid: usize, timeout: Duration) -> (Sender<()>, JoinHandle<bool>) {
+    let (done, done_rx) = mpsc::channel();
+    let timer = thread::spawn(move || {
+        if done_rx.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+            return false;
+        }
+        debug!("contain: timed out, terminating group {}", pgid);
+        let _ = kill_group(pgid, libc::SIGTERM);
+        if done_rx.recv_timeout(KILL_GRACE_PERIOD) == Err(RecvTimeoutError::Timeout) {
+            debug!("contain: timed out, killing group {}", pgid);
+            let _ = kill_group(pgid, libc::SIGKILL);
+        }
+        true
+    });
+    (done, timer)
+}
+
+// The pids of the processes in the namespace that are not in the group,
+// from a listing of sys:context. None if the listing has no PID, PGID or ENS column.
+fn outside_group(contexts: &str, namespace: usize, pgid: usize) -> Option<Vec<usize>> {
+    let mut lines = contexts.lines();
+    let header: Vec<&str> = lines.next()?.split_whitespace().collect();
+    let column = |name| header.iter().position(|column| *column == name);
+    let (pid_column, pgid_column, ns_column) = (column("PID")?, column("PGID")?, column("ENS")?);
+    let pids = lines
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let field = |i: usize| fields.get(i)?.parse::<usize>().ok();
+            let (pid, group, ns) = (field(pid_column)?, field(pgid_column)?, field(ns_column)?);
+            (ns == namespace && group != pgid).then_some(pid)
+        })
+        .collect();
+    Some(pids)
+}
+
+// The processes in the namespace, if there is one, that left the group.
+// Empty if the processes can't be listed.
+fn strays(pgid: usize, namespace: Option<usize>) -> Vec<usize> {
+    let Some(namespace) = namespace else {
+        return Vec::new();
+    };
+    let contexts = match fs::read_to_string("sys:context") {
+        Ok(contexts) => contexts,
+        Err(e) => {
+            debug!("contain: could not list processes: {}", e);
+            return Vec::new();
+        }
+    };
+    outside_group(&contexts, namespace, pgid).unwrap_or_else(|| {
+        debug!("contain: could not find the namespace of each process");
+        Vec::new()
+    })
+}
+
+// True if any process is left in the group, or in the namespace if there is one
+fn container_alive(pgid: usize, namespace: Option<usize>) -> bool {
+    group_alive(pgid) || !strays(pgid, namespace).is_empty()
+}
+
+// Signal the group, and the processes in the namespace that left it
+fn signal_container(pgid: usize, namespace: Option<usize>, signal: libc::c_int) {
+    let _ = kill_group(pgid, signal);
+    for pid in strays(pgid, namespace) {
+        unsafe { libc::kill(pid as libc::pid_t, signal) };
+    }
+}
+
+// Wait for the group, and the namespace if there is one, to be empty,
+// or until the time is up. Returns true if it is empty.
+fn wait_for_container(pgid: usize, namespace: Option<usize>, timeout: Option<Duration>) -> bool {
+    let start = Instant::now();
+    while container_alive(pgid, namespace) {
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            return false;
+        }
+        thread::sleep(GROUP_POLL_INTERVAL);
+    }
+    true
+}
+
+// Deal with the processes left in the container's process group
+// once the command has exited, see `OnExit`.
+// With a namespace, the processes in it that left the group are dealt with too.
+pub(crate) fn end_group(pgid: usize, namespace: Option<usize>, on_exit: OnExit) {
+    match on_exit {
+        OnExit::Detach => {
+            if group_alive(pgid) {
+                debug!("contain: leaving container processes in group {}", pgid);
+            }
+            let strays = strays(pgid, namespace);
+            if !strays.is_empty() {
+                warn!(
+                    "contain: leaving processes {:?} that left process group {} running",
+                    strays, pgid
+                );
+            }
+        }
+        OnExit::Wait => {
+            debug!("contain: waiting for container processes in group {}", pgid);
+            wait_for_container(pgid, namespace, None);
+        }
+        OnExit::Kill => {
+            if !container_alive(pgid, namespace) {
+                return;
+            }
+            debug!("contain: terminating container processes in group {}", pgid);
+            signal_container(pgid, namespace, libc::SIGTERM);
+            if !wait_for_container(pgid, namespace, Some(KILL_GRACE_PERIOD)) {
+                debug!("contain: killing container processes in group {}", pgid);
+                signal_container(pgid, namespace, libc::SIGKILL);
+                wait_for_container(pgid, namespace, Some(KILL_GRACE_PERIOD));
+            }
+        }
+    }
+}
+
+// Spawn the command in the namespace, in a new process group
+// that does not get the terminal, keeping the pipes the command asks for.
+// The command's working directory is changed to inside the namespace.
+// Its uid and gid, if set, would be applied before entering the namespace,
+// which needs root, so switch user with `run_as` instead.
+// With `run_as`, the command runs as that user.
+// If the child can't enter the namespace or switch user, `SetupFailed` is returned,
+// and if the command can't be executed, `ExecFailed`.
+pub(crate) fn spawn_in_namespace(
+    command: &mut Command,
+    namespace: usize,
+    run_as: Option<&RunAs>,
+) -> ContainResult<Child> {
+    if let Some(run_as) = run_as {
+        run_as.set_env(command);
+    }
+    // std changes directory before running the setup, so do it in the setup instead
+    let cwd = match command.get_current_dir() {
+        Some(cwd) => Some(CString::new(cwd.as_os_str().as_bytes()).map_err(|e| {
+            error!("contain: invalid working directory {:?}", cwd);
+            ContainError::io_error(e.into())
+        })?),
+        None => None,
+    };
+    if cwd.is_some() {
+        command.current_dir(".");
+    }
+    let (read_fd, write_fd) = report_pipe()?;
+    let switch_user = run_as.map(RunAs::switch_user);
+    command.process_group(0);
+    unsafe {
+        command.pre_exec(child_setup(
+            Some(namespace),
+            cwd,
+            switch_user,
+            false,
+            write_fd,
+        ));
+    }
+
+    let spawned = command.spawn();
+    unsafe { libc::close(write_fd) };
+    match spawned {
+        Ok(child) => {
+            unsafe { libc::close(read_fd) };
+            Ok(child)
+        }
+        Err(e) => match read_report(read_fd) {
+            Some(e) => {
+                error!(
+                    "child failed to enter namespace {} or switch user: {}",
+                    namespace, e
+                );
+                Err(e)
+            }
+            None => {
+                error!("failed to launch {:?}: {}", command, e);
+                Err(ContainError::ExecFailed(e))
+            }
+        },
+    }
+}
+
+// Fork a child that enters the namespace and then executes the command,
+// in a new process group. Unlike `spawn_in_namespace`, the namespace is entered
+// before the command's uid, gid and working directory are applied,
+// so a command made by `User::shell_cmd` works, but it only inherits stdio.
+// With `run_as`, the command runs as that user.
+// If `foreground`, the new process group is given the terminal.
+// Returns the pid, or `SetupFailed` or `ExecFailed` from the child,
+// which always exits if it fails.
+fn fork_in_namespace(
+    command: &mut Command,
+    namespace: usize,
+    run_as: Option<&RunAs>,
+    foreground: bool,
+) -> ContainResult<usize> {
+    if let Some(run_as) = run_as {
+        run_as.set_env(command);
+    }
+    let (read_fd, write_fd) = report_pipe()?;
+    let switch_user = run_as.map(RunAs::switch_user);
+    command.process_group(0);
+    unsafe {
+        command.pre_exec(child_setup(None, None, switch_user, foreground, write_fd));
+    }
+
     let pid = unsafe { libc::fork() };
     if pid == -1 {
         let e = std::io::Error::last_os_error();
         error!("contain: fork failed, {}", e);
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
         return Err(ContainError::io_error(e));
     }
-    let pid = pid as usize;
     if pid == 0 {
-        syscall::setrens(namespace, namespace).map_err(|e| {
-            error!("child failed to enter restricted namespace, {}", e);
-            ContainError::syscall_error(e)
-        })?;
-
-        let err = command.exec();
-
-        error!("failed to launch {:?}: {}", command, err);
-        exit(CONTAIN_EXEC_FAIL_EXIT);
-    } else {
-        let mut status = 0;
-        let _ = waitpid(pid, &mut status, 0).map_err(|e| {
-            error!("waitpid({}) returned error: {}", pid, e);
-            ContainError::syscall_error(e)
-        })?;
-
-        loop {
-            let mut c_status = 0;
-            let c_pid = waitpid(0, &mut c_status, libc::WNOHANG).unwrap_or_else(|e| {
-                error!("waitpid(any) returned error: {}", e);
-                0
-            });
-            if c_pid == 0 {
-                break;
-            } else {
-                debug!("contain: container zombie {}: {:X}", c_pid, c_status);
-            }
-        }
-
-        debug!(
-            "contain: Container {}, pid {}: exit: {:X}",
-            namespace, pid, status
+        if let Err(e) = syscall::setrens(namespace, namespace) {
+            report_failure(
+                write_fd,
+                REPORT_SETUP,
+                std::io::Error::from_raw_os_error(e.errno),
+            );
+            unsafe { libc::_exit(CHILD_FAIL_EXIT) };
+        }
+        let e = command.exec();
+        report_failure(write_fd, REPORT_EXEC, e);
+        unsafe { libc::_exit(CHILD_FAIL_EXIT) };
+    }
+
+    unsafe { libc::close(write_fd) };
+    if foreground {
+        // also done in the child, whichever runs first
+        unsafe { libc::setpgid(pid, pid) };
+        give_terminal(pid);
+    }
+    match read_report(read_fd) {
+        Some(e) => {
+            let _ = wait_pid(pid as usize);
+            match e {
+                ContainError::ExecFailed(_) => error!("failed to launch {:?}: {}", command, e),
+                _ => error!(
+                    "child failed to enter namespace {} or switch user: {}",
+                    namespace, e
+                ),
+            }
+            Err(e)
+        }
+        None => Ok(pid as usize),
+    }
+}
+
+// Wait for the child to exit, returning its waitpid status
+fn wait_pid(pid: usize) -> std::io::Result<i32> {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid as libc::pid_t, &mut status, 0) } != -1 {
+            return Ok(status);
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// After the new namespace has been created, run the command in that namespace.
+/// The command's uid, gid and working directory are applied once the child
+/// is in the namespace, so a command from `User::shell_cmd` can be used.
+/// With `run_as`, the command runs as that user, and is not run at all
+/// if the child can't switch to the user.
+/// If the child can't enter the namespace or switch user, `SetupFailed` is returned,
+/// and if the command can't be executed, `ExecFailed`.
+/// The command runs in a new process group, so every process it starts can be found,
+/// and the group gets the terminal if contain has it, until the group has ended.
+/// A Ctrl-C on the terminal goes to the group. Signals sent to contain are forwarded
+/// to the group, see `FORWARDED_SIGNALS`, and a second SIGINT kills the group.
+/// A command killed by SIGINT counts as the first one.
+/// Only one command at a time can have its signals forwarded. When the command exits,
+/// the processes that are left are handled according to `on_exit`,
+/// or killed if the command was interrupted.
+/// This includes the processes in the namespace that left the group, see `OnExit`.
+/// With a `timeout`, the container is killed once the time is up,
+/// and `TimedOut` is returned.
+/// Once the command completes, terminate the namesapce thread.
+pub fn run_in_namespace(
+    command: Command,
+    namespace: usize,
+    run_as: Option<&RunAs>,
+    on_exit: OnExit,
+    timeout: Option<Duration>,
+) -> ContainResult<ContainExit> {
+    run_command(command, namespace, run_as, on_exit, timeout, true)
+}
+
+// Run the command like `run_in_namespace`. Unless `whole_container`,
+// only the command's own process group is ended when it exits.
+fn run_command(
+    mut command: Command,
+    namespace: usize,
+    run_as: Option<&RunAs>,
+    on_exit: OnExit,
+    timeout: Option<Duration>,
+    whole_container: bool,
+) -> ContainResult<ContainExit> {
+    let foreground = in_foreground();
+    let pid = match fork_in_namespace(&mut command, namespace, run_as, foreground) {
+        Ok(pid) => pid,
+        Err(e) => {
+            if foreground {
+                give_terminal(unsafe { libc::getpgrp() });
+            }
+            return Err(e);
+        }
+    };
+
+    let forwarder = SignalForwarder::new(pid);
+    let timer = timeout.map(|timeout| start_timer(pid, timeout));
+    let waited = wait_pid(pid).map_err(|e| {
+        error!("wait for {} returned error: {}", pid, e);
+        ContainError::io_error(e)
+    });
+
+    let timed_out = timer.is_some_and(|(done, timer)| {
+        drop(done);
+        timer.join().unwrap_or(false)
+    });
+    // a Ctrl-C on the terminal reached the group without going through contain
+    let interrupted = waited
+        .as_ref()
+        .is_ok_and(|status| ContainExit::from_status(*status).signal == Some(libc::SIGINT));
+    if let Some(forwarder) = forwarder.as_ref().filter(|_| interrupted) {
+        forwarder.interrupted();
+    }
+    end_group(
+        pid,
+        Some(namespace).filter(|_| whole_container),
+        if timed_out || interrupted {
+            OnExit::Kill
+        } else {
+            on_exit
+        },
+    );
+    if foreground {
+        give_terminal(unsafe { libc::getpgrp() });
+    }
+    drop(forwarder);
+    let status = ContainExit::from_status(waited?);
+    if timed_out {
+        error!("contain: container {}, pid {}: timed out", namespace, pid);
+        return Err(ContainError::TimedOut);
+    }
+
+    debug!(
+        "contain: Container {}, pid {}: exit: {}",
+        namespace, pid, status
+    );
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schemes() -> Vec<String> {
+        ["file", "rand", "null"].map(String::from).to_vec()
+    }
+
+    fn config() -> ContainConfig {
+        ContainConfig {
+            pass_schemes: ["rand", "rnad", "rand"].map(String::from).to_vec(),
+            sandbox_schemes: vec!["file".to_string()],
+            dirs: ["file:/tmp", "fiel:/home"].map(String::from).to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn warns_about_dropped_entries() {
+        let (config, diagnostics) = validate_with_schemes(config(), &schemes()).unwrap();
+        assert_eq!(config.pass_schemes, ["rand"]);
+        assert_eq!(config.dirs, ["file:/tmp"]);
+        let found: Vec<(DiagnosticKind, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.kind, d.entry.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (DiagnosticKind::UnknownScheme, "rnad"),
+                (DiagnosticKind::NotSandboxed, "fiel:/home")
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_malformed_entries() {
+        let mut config = config();
+        config.pass_schemes.push("null:".to_string());
+        config.files.push("file:tmp/x".to_string());
+        let (config, diagnostics) = validate_with_schemes(config, &schemes()).unwrap();
+        assert_eq!(config.pass_schemes, ["rand"]);
+        assert!(config.files.is_empty());
+        assert!(diagnostics.contains(&ConfigDiagnostic::new(
+            DiagnosticKind::InvalidScheme,
+            "null:",
+            &config
+        )));
+        assert!(diagnostics.contains(&ConfigDiagnostic::new(
+            DiagnosticKind::NotAPath,
+            "file:tmp/x",
+            &config
+        )));
+    }
+
+    #[test]
+    fn strict_rejects_dropped_entries() {
+        let mut strict = config();
+        strict.strict = Some(true);
+        match validate_with_schemes(strict, &schemes()) {
+            Err(ContainError::InvalidConfig(diagnostics)) => assert_eq!(diagnostics.len(), 2),
+            other => panic!("expected InvalidConfig, got {:?}", other),
+        }
+
+        let mut valid = config();
+        valid.pass_schemes = vec!["rand".to_string()];
+        valid.dirs = vec!["file:/tmp".to_string()];
+        valid.strict = Some(true);
+        let (_, diagnostics) = validate_with_schemes(valid, &schemes()).unwrap();
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn decodes_exit_status() {
+        let exited = ContainExit::from_status(3 << 8);
+        assert_eq!(exited.code, Some(3));
+        assert_eq!(exited.exit_code(), 3);
+        assert!(!ContainExit::from_status(1 << 8).success());
+        assert!(ContainExit::from_status(0).success());
+
+        let killed = ContainExit::from_status(9);
+        assert_eq!(killed.signal, Some(9));
+        assert_eq!(killed.exit_code(), 137);
+        assert_eq!(
+            ContainExit::from_status(11 | 0x80).to_string(),
+            "killed by signal 11 (core dumped)"
+        );
+    }
+
+    #[test]
+    fn times_out() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let (done, timer) = start_timer(child.id() as usize, Duration::from_millis(10));
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGTERM));
+        drop(done);
+        assert!(timer.join().unwrap());
+
+        let (done, timer) = start_timer(child.id() as usize, Duration::from_secs(30));
+        drop(done);
+        assert!(!timer.join().unwrap());
+    }
+
+    #[test]
+    fn finds_processes_outside_group() {
+        let contexts = "PID   PGID  PPID  RUID  RGID  RNS   EUID  EGID  ENS   STAT  NAME\n\
+                        1     1     0     0     0     1     0     0     1     R     init\n\
+                        20    20    1     0     0     1     0     0     4     S     ion\n\
+                        21    20    20    0     0     1     0     0     4     S     sleep\n\
+                        22    22    21    0     0     1     0     0     4     S     daemon\n";
+        assert_eq!(outside_group(contexts, 4, 20), Some(vec![22]));
+        assert_eq!(outside_group(contexts, 5, 20), Some(vec![]));
+        assert_eq!(outside_group("PID NAME\n1 init\n", 4, 20), None);
+    }
+
+    #[test]
+    fn reads_child_report() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        report_failure(
+            fds[1],
+            REPORT_SETUP,
+            std::io::Error::from_raw_os_error(libc::EPERM),
         );
-        Ok(status)
+        unsafe { libc::close(fds[1]) };
+        match read_report(fds[0]) {
+            Some(ContainError::SetupFailed(e)) => assert_eq!(e.raw_os_error(), Some(libc::EPERM)),
+            e => panic!("expected SetupFailed, got {:?}", e),
+        }
+
+        // the first report counts
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let enoent = || std::io::Error::from_raw_os_error(libc::ENOENT);
+        report_failure(fds[1], REPORT_EXEC, enoent());
+        report_failure(fds[1], REPORT_SETUP, enoent());
+        unsafe { libc::close(fds[1]) };
+        match read_report(fds[0]) {
+            Some(ContainError::ExecFailed(e)) => assert_eq!(e.raw_os_error(), Some(libc::ENOENT)),
+            e => panic!("expected ExecFailed, got {:?}", e),
+        }
+
+        // nothing is written if the command was executed
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        unsafe { libc::close(fds[1]) };
+        assert!(read_report(fds[0]).is_none());
     }
 }
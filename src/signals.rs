@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use log::debug;
+
+/// The signals that contain passes on to the container
+pub const FORWARDED_SIGNALS: [libc::c_int; 6] = [
+    libc::SIGINT,
+    libc::SIGTERM,
+    libc::SIGHUP,
+    libc::SIGQUIT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+// The process group to forward to, 0 if there is none.
+// Only one forwarder can be active, see `SignalForwarder::new`.
+static FORWARD_PGID: AtomicI32 = AtomicI32::new(0);
+// The number of SIGINTs received while forwarding
+static SIGINT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Only async-signal-safe calls here
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pgid = FORWARD_PGID.load(Ordering::SeqCst);
+    if pgid <= 0 {
+        return;
+    }
+    let signal = if signal == libc::SIGINT && SIGINT_COUNT.fetch_add(1, Ordering::SeqCst) > 0 {
+        // a second SIGINT kills the container
+        libc::SIGKILL
+    } else {
+        signal
+    };
+    unsafe { libc::kill(-pgid, signal) };
+}
+
+/// Forwards `FORWARDED_SIGNALS` to a process group until it is dropped.
+/// The second SIGINT is sent as SIGKILL, counting the ones reported by `interrupted`.
+/// The previous handlers are restored when it is dropped.
+pub(crate) struct SignalForwarder {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl SignalForwarder {
+    /// Start forwarding to the process group.
+    /// The handlers are process-wide, so only one forwarder can be active,
+    /// and `None` is returned while another one is.
+    pub(crate) fn new(pgid: usize) -> Option<Self> {
+        if FORWARD_PGID
+            .compare_exchange(0, pgid as i32, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            debug!("already forwarding signals, not forwarding to {}", pgid);
+            return None;
+        }
+        SIGINT_COUNT.store(0, Ordering::SeqCst);
+        let mut previous = Vec::new();
+        for signal in FORWARDED_SIGNALS {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction =
+                    forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut old: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(signal, &action, &mut old) == 0 {
+                    previous.push((signal, old));
+                } else {
+                    debug!(
+                        "could not forward signal {}: {}",
+                        signal,
+                        std::io::Error::last_os_error()
+                    );
+                }
+            }
+        }
+        Some(Self { previous })
+    }
+
+    /// Count a SIGINT that the group got without contain, from the terminal
+    pub(crate) fn interrupted(&self) {
+        SIGINT_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for SignalForwarder {
+    fn drop(&mut self) {
+        for (signal, old) in self.previous.iter() {
+            unsafe { libc::sigaction(*signal, old, std::ptr::null_mut()) };
+        }
+        FORWARD_PGID.store(0, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::Command;
+
+    #[test]
+    fn forwards_and_escalates() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let forwarder = SignalForwarder::new(child.id() as usize).unwrap();
+        assert!(SignalForwarder::new(1).is_none());
+        unsafe { libc::raise(libc::SIGTERM) };
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGTERM));
+        drop(forwarder);
+
+        // a process that ignores SIGINT is killed by the second one
+        let mut command = Command::new("sleep");
+        command.arg("30").process_group(0);
+        unsafe {
+            command.pre_exec(|| {
+                libc::signal(libc::SIGINT, libc::SIG_IGN);
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().unwrap();
+        let forwarder = SignalForwarder::new(child.id() as usize).unwrap();
+        unsafe { libc::raise(libc::SIGINT) };
+        assert!(child.try_wait().unwrap().is_none());
+        unsafe { libc::raise(libc::SIGINT) };
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+        drop(forwarder);
+
+        // a Ctrl-C the group got from the terminal counts as the first SIGINT
+        let mut child = command.spawn().unwrap();
+        let forwarder = SignalForwarder::new(child.id() as usize).unwrap();
+        forwarder.interrupted();
+        unsafe { libc::raise(libc::SIGINT) };
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+    }
+}